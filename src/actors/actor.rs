@@ -1,5 +1,6 @@
 /// A WebSocket actor for handling real-time chat messages.
-use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use crate::models::shutdown::ShutdownSignal;
+use actix::{Actor, ActorContext, ActorFutureExt, AsyncContext, StreamHandler, WrapFuture};
 use actix_web_actors::ws;
 use ammonia::clean;
 use serde_json::Value;
@@ -9,7 +10,17 @@ use serde_json::Value;
 /// `ChatSocket` is an actor that uses Actix's WebSocket implementation to handle
 /// incoming WebSocket messages. It is capable of processing text and binary messages,
 /// as well as handling connection closure and continuation frames.
-pub struct ChatSocket;
+/// When the server shuts down, the socket is closed with a "going away" close frame.
+pub struct ChatSocket {
+    shutdown: Option<ShutdownSignal>,
+}
+
+impl ChatSocket {
+    /// Creates a new ChatSocket that closes once `shutdown` resolves.
+    pub fn new(shutdown: ShutdownSignal) -> Self {
+        ChatSocket { shutdown: Some(shutdown) }
+    }
+}
 
 impl Actor for ChatSocket {
     type Context = ws::WebsocketContext<Self>;
@@ -27,7 +38,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatSocket {
             Ok(ws::Message::Text(text)) => {
                 if let Ok(parsed) = serde_json::from_str::<Value>(&text) {
                     if let Some(chat_message) = parsed["chat_message"].as_str() {
-                        let sanitized_message = clean(&chat_message);
+                        let sanitized_message = clean(chat_message);
                         ctx.text(format!(
                             "
                             <div id=\"chat_room\" hx-swap-oob=\"beforeend\">{}<br></div>\n
@@ -61,5 +72,14 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatSocket {
     fn started(&mut self, ctx: &mut Self::Context) {
         ctx.text("Hello world!");
         println!("Connected: {:?}", ctx.address());
+        if let Some(shutdown) = self.shutdown.take() {
+            ctx.spawn(shutdown.recv().into_actor(self).map(|_, _, ctx| {
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Away,
+                    description: Some("Server is shutting down".to_string()),
+                }));
+                ctx.stop();
+            }));
+        }
     }
 }
@@ -1,2 +1,521 @@
+/// Typed application configuration loaded from the environment and an optional TOML file.
+use reqwest::Url;
+use rustls::{Certificate, PrivateKey};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
+/// Path of the TOML configuration file used when `APP_CONFIG` is not set.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
 
+/// Settings for the HTTP server.
+pub struct ServerConfig {
+    pub host: String,
+    /// Port of the plain HTTP listener. With TLS enabled it only redirects to HTTPS.
+    pub port: u16,
+    pub tls: Option<TlsConfig>,
+    /// How long open WebSocket and SSE connections get to close on shutdown.
+    pub shutdown_drain: Duration,
+}
+
+/// Settings for serving HTTPS with rustls.
+pub struct TlsConfig {
+    /// PEM file holding the certificate chain.
+    pub cert_path: String,
+    /// PEM file holding the private key (PKCS#8, RSA or SEC1).
+    pub key_path: String,
+    pub https_port: u16,
+    /// Run a plain HTTP listener on `ServerConfig::port` redirecting to HTTPS.
+    pub redirect_http: bool,
+    /// `max-age` of the `Strict-Transport-Security` header; `None` disables HSTS.
+    pub hsts_max_age: Option<u64>,
+}
+
+impl TlsConfig {
+    /// Settings belonging to TLS.
+    const KEYS: &'static [(&'static str, &'static str)] =
+        &[("TLS_CERT_PATH", "server.tls.cert_path"), ("TLS_KEY_PATH", "server.tls.key_path")];
+
+    /// Loads the TLS settings, or `None` if HTTPS is not configured.
+    fn load(settings: &mut Settings) -> Option<Self> {
+        if !settings.any(Self::KEYS) {
+            return None;
+        }
+        let cert_path = settings.file_path("TLS_CERT_PATH", "server.tls.cert_path");
+        let key_path = settings.file_path("TLS_KEY_PATH", "server.tls.key_path");
+        let hsts_max_age =
+            settings.parsed("HSTS_MAX_AGE", "server.tls.hsts_max_age", 31_536_000u64);
+        Some(TlsConfig {
+            cert_path,
+            key_path,
+            https_port: settings.parsed("HTTPS_PORT", "server.tls.port", 8443),
+            redirect_http: settings.parsed("TLS_REDIRECT_HTTP", "server.tls.redirect_http", true),
+            hsts_max_age: Some(hsts_max_age).filter(|max_age| *max_age > 0),
+        })
+    }
+
+    /// Reads the certificate chain and private key into a rustls server configuration.
+    pub fn rustls_config(&self) -> io::Result<rustls::ServerConfig> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        let open = |path: &str| {
+            File::open(path).map(BufReader::new).map_err(|e| invalid(format!("{}: {}", path, e)))
+        };
+
+        let mut cert_reader = open(&self.cert_path)?;
+        let certs: Vec<Certificate> =
+            rustls_pemfile::certs(&mut cert_reader)?.into_iter().map(Certificate).collect();
+        if certs.is_empty() {
+            return Err(invalid(format!("no certificates found in {}", self.cert_path)));
+        }
+
+        let mut key_reader = open(&self.key_path)?;
+        let key = rustls_pemfile::read_all(&mut key_reader)?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or_else(|| invalid(format!("no private key found in {}", self.key_path)))?;
+
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// Settings for the Supabase integration.
+#[derive(Clone)]
+pub struct SupabaseConfig {
+    /// PostgREST endpoint, e.g. `https://<project>.supabase.co/rest/v1`.
+    pub url: String,
+    /// GoTrue endpoint, derived from `url` unless set explicitly.
+    pub auth_url: String,
+    pub public_key: String,
+    pub service_role_key: Option<String>,
+}
+
+/// Settings for the Sanity integration.
+#[derive(Clone)]
+pub struct SanityConfig {
+    pub project_id: String,
+    pub dataset: String,
+    pub token: String,
+    /// Dated API version such as `v2021-10-21`.
+    pub api_version: String,
+    /// Query the cached API CDN instead of the live API.
+    pub use_cdn: bool,
+    /// Timeout of a single request to Sanity.
+    pub timeout: Duration,
+    /// How often a failed query is retried, at most 10 times.
+    pub max_retries: u32,
+    /// How long query results are served from the cache; zero disables caching.
+    pub cache_ttl: Duration,
+    /// How long expired results are still served while they are refreshed.
+    pub cache_stale_while_revalidate: Duration,
+    /// How many query results are cached at most.
+    pub cache_max_entries: usize,
+    /// Secret signing the requests of the Sanity webhook; `None` disables `/webhooks/sanity`.
+    pub webhook_secret: Option<String>,
+}
+
+/// Backend used to persist server-side sessions.
+#[derive(PartialEq)]
+pub enum SessionStoreKind {
+    Memory,
+    Postgrest,
+}
+
+impl FromStr for SessionStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(SessionStoreKind::Memory),
+            "postgrest" => Ok(SessionStoreKind::Postgrest),
+            _ => Err(format!("unknown session store `{}`, expected `memory` or `postgrest`", s)),
+        }
+    }
+}
+
+/// The environment the application runs in, selected with `APP_ENV`.
+///
+/// The profile provides defaults for settings that differ between environments.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Profile {
+    Development,
+    Staging,
+    Production,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dev" | "development" => Ok(Profile::Development),
+            "staging" => Ok(Profile::Staging),
+            "prod" | "production" => Ok(Profile::Production),
+            _ => Err(format!("unknown profile `{}`, expected `dev`, `staging` or `prod`", s)),
+        }
+    }
+}
+
+impl Profile {
+    /// Returns the default Sanity dataset of the profile.
+    fn sanity_dataset(self) -> &'static str {
+        match self {
+            Profile::Development => "development",
+            Profile::Staging => "staging",
+            Profile::Production => "production",
+        }
+    }
+}
+
+/// Format of the log output.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, for log aggregation.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{}`, expected `text` or `json`", s)),
+        }
+    }
+}
+
+/// Settings for login brute-force protection.
+pub struct LoginThrottleConfig {
+    pub free_attempts: u32,
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+/// The complete application configuration.
+///
+/// Every setting can be given as an environment variable (also read from `.env`)
+/// or in the TOML file named by `APP_CONFIG` (default `config.toml`, optional).
+/// Environment variables take precedence over the file.
+///
+/// The Supabase and Sanity integrations are optional: an integration none of whose
+/// settings are given is disabled, while a partially configured one is an error.
+pub struct AppConfig {
+    pub profile: Profile,
+    /// Watch the templates and reload them, and open pages, on change. Defaults to on in development.
+    pub template_hot_reload: bool,
+    /// Show error details such as template errors to the client. Defaults to on in development.
+    pub detailed_errors: bool,
+    /// Mark cookies `Secure`. Defaults to off in development, which is served over plain HTTP.
+    pub secure_cookies: bool,
+    /// Log output format. Defaults to JSON in production.
+    pub log_format: LogFormat,
+    pub server: ServerConfig,
+    pub supabase: Option<SupabaseConfig>,
+    pub sanity: Option<SanityConfig>,
+    pub session_store: SessionStoreKind,
+    pub login_throttle: LoginThrottleConfig,
+}
+
+/// An error listing every missing or invalid setting.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads raw settings from the environment and the TOML file, collecting problems.
+struct Settings {
+    file: toml::Table,
+    problems: Vec<String>,
+}
+
+impl Settings {
+    /// Looks up a setting by environment variable, then by dotted path in the file.
+    fn raw(&self, env: &str, path: &str) -> Option<String> {
+        if let Ok(value) = std::env::var(env) {
+            return Some(value);
+        }
+        let mut value = self.file.get(path.split('.').next()?)?;
+        for key in path.split('.').skip(1) {
+            value = value.get(key)?;
+        }
+        match value {
+            toml::Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+
+    /// Returns an optional setting.
+    fn optional(&self, env: &str, path: &str) -> Option<String> {
+        self.raw(env, path).filter(|value| !value.is_empty())
+    }
+
+    /// Returns a required setting, recording a problem if it is missing.
+    fn required(&mut self, env: &str, path: &str) -> String {
+        self.optional(env, path).unwrap_or_else(|| {
+            self.problems.push(format!("{} (`{}` in the config file) is not set", env, path));
+            String::new()
+        })
+    }
+
+    /// Returns a parsed setting or `default`, recording a problem if parsing fails.
+    fn parsed<T>(&mut self, env: &str, path: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.optional(env, path) {
+            Some(value) => value.parse().unwrap_or_else(|e| {
+                self.problems.push(format!("{} has invalid value `{}`: {}", env, value, e));
+                default
+            }),
+            None => default,
+        }
+    }
+
+    /// Returns true if any of the given `(env, path)` settings is present.
+    fn any(&self, keys: &[(&str, &str)]) -> bool {
+        keys.iter().any(|(env, path)| self.optional(env, path).is_some())
+    }
+
+    /// Returns a required path setting, recording a problem if it is missing or no file exists there.
+    fn file_path(&mut self, env: &str, path: &str) -> String {
+        let value = self.required(env, path);
+        if !value.is_empty() && !Path::new(&value).is_file() {
+            self.problems.push(format!("{} points to `{}`, which is not a file", env, value));
+        }
+        value
+    }
+
+    /// Returns a required URL setting, recording a problem if it is missing or malformed.
+    fn url(&mut self, env: &str, path: &str) -> String {
+        let value = self.required(env, path);
+        if !value.is_empty() {
+            if let Err(e) = Url::parse(&value) {
+                self.problems.push(format!("{} is not a valid URL: {}", env, e));
+            }
+        }
+        value
+    }
+}
+
+impl SupabaseConfig {
+    /// Settings belonging to the Supabase integration.
+    const KEYS: &'static [(&'static str, &'static str)] = &[
+        ("SUPABASE_URL", "supabase.url"),
+        ("SUPABASE_AUTH_URL", "supabase.auth_url"),
+        ("SUPABASE_PUBLIC_KEY", "supabase.public_key"),
+        ("SUPABASE_SERVICE_ROLE_KEY", "supabase.service_role_key"),
+    ];
+
+    /// Loads the Supabase settings, or `None` if the integration is not configured.
+    fn load(settings: &mut Settings) -> Option<Self> {
+        if !settings.any(Self::KEYS) {
+            return None;
+        }
+        let url = settings.url("SUPABASE_URL", "supabase.url");
+        let auth_url = match settings.optional("SUPABASE_AUTH_URL", "supabase.auth_url") {
+            Some(_) => settings.url("SUPABASE_AUTH_URL", "supabase.auth_url"),
+            None => format!("{}/auth/v1", url.trim_end_matches('/').trim_end_matches("/rest/v1")),
+        };
+        Some(SupabaseConfig {
+            url,
+            auth_url,
+            public_key: settings.required("SUPABASE_PUBLIC_KEY", "supabase.public_key"),
+            service_role_key: settings
+                .optional("SUPABASE_SERVICE_ROLE_KEY", "supabase.service_role_key"),
+        })
+    }
+}
+
+impl SanityConfig {
+    /// Settings belonging to the Sanity integration.
+    const KEYS: &'static [(&'static str, &'static str)] = &[
+        ("SANITY_PROJECT_ID", "sanity.project_id"),
+        ("SANITY_DATASET", "sanity.dataset"),
+        ("SANITY_TOKEN_KEY", "sanity.token"),
+    ];
+
+    /// Upper bound of `max_retries`.
+    const MAX_RETRIES: u32 = 10;
+
+    /// Loads the Sanity settings, or `None` if the integration is not configured.
+    ///
+    /// The dataset defaults to `default_dataset`.
+    fn load(settings: &mut Settings, default_dataset: &str) -> Option<Self> {
+        if !settings.any(Self::KEYS) {
+            return None;
+        }
+        let max_retries = settings.parsed("SANITY_MAX_RETRIES", "sanity.max_retries", 2);
+        if max_retries > Self::MAX_RETRIES {
+            settings.problems.push(format!(
+                "SANITY_MAX_RETRIES must be at most {}, got {}",
+                Self::MAX_RETRIES,
+                max_retries
+            ));
+        }
+        Some(SanityConfig {
+            project_id: settings.required("SANITY_PROJECT_ID", "sanity.project_id"),
+            dataset: settings
+                .optional("SANITY_DATASET", "sanity.dataset")
+                .unwrap_or(default_dataset.to_string()),
+            token: settings.required("SANITY_TOKEN_KEY", "sanity.token"),
+            api_version: settings
+                .optional("SANITY_API_VERSION", "sanity.api_version")
+                .unwrap_or("v2021-10-21".to_string()),
+            use_cdn: settings.parsed("SANITY_USE_CDN", "sanity.use_cdn", true),
+            timeout: Duration::from_secs(settings.parsed(
+                "SANITY_TIMEOUT_SECS",
+                "sanity.timeout_secs",
+                10,
+            )),
+            max_retries,
+            cache_ttl: Duration::from_secs(settings.parsed(
+                "SANITY_CACHE_TTL_SECS",
+                "sanity.cache_ttl_secs",
+                60,
+            )),
+            cache_stale_while_revalidate: Duration::from_secs(settings.parsed(
+                "SANITY_CACHE_STALE_SECS",
+                "sanity.cache_stale_secs",
+                300,
+            )),
+            cache_max_entries: settings.parsed(
+                "SANITY_CACHE_MAX_ENTRIES",
+                "sanity.cache_max_entries",
+                1000,
+            ),
+            webhook_secret: settings.optional("SANITY_WEBHOOK_SECRET", "sanity.webhook_secret"),
+        })
+    }
+}
+
+impl AppConfig {
+    /// Loads and validates the configuration.
+    ///
+    /// Reads `.env`, then the optional TOML file, and reports all missing or invalid
+    /// settings at once.
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+
+        let (path, explicit) = match std::env::var("APP_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+        let mut settings = Settings { file: toml::Table::new(), problems: Vec::new() };
+        if explicit || Path::new(&path).exists() {
+            match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| content.parse::<toml::Table>().map_err(|e| e.to_string()))
+            {
+                Ok(file) => settings.file = file,
+                Err(e) => settings.problems.push(format!("cannot read {}: {}", path, e)),
+            }
+        }
+
+        let profile = settings.parsed("APP_ENV", "app.env", Profile::Development);
+        let development = profile == Profile::Development;
+        let template_hot_reload =
+            settings.parsed("TEMPLATE_HOT_RELOAD", "app.template_hot_reload", development);
+        let detailed_errors =
+            settings.parsed("DETAILED_ERRORS", "app.detailed_errors", development);
+        let secure_cookies = settings.parsed("SECURE_COOKIES", "app.secure_cookies", !development);
+        let default_log_format =
+            if profile == Profile::Production { LogFormat::Json } else { LogFormat::Text };
+        let log_format = settings.parsed("LOG_FORMAT", "app.log_format", default_log_format);
+
+        let server = ServerConfig {
+            host: settings.optional("APP_HOST", "server.host").unwrap_or("127.0.0.1".to_string()),
+            port: settings.parsed("APP_PORT", "server.port", 8080),
+            tls: TlsConfig::load(&mut settings),
+            shutdown_drain: Duration::from_secs(settings.parsed(
+                "SHUTDOWN_DRAIN_SECS",
+                "server.shutdown_drain_secs",
+                5,
+            )),
+        };
+
+        let supabase = SupabaseConfig::load(&mut settings);
+        // Deployments from before the profiles set no `APP_ENV` and queried `production`
+        let default_dataset = match settings.optional("APP_ENV", "app.env") {
+            Some(_) => profile.sanity_dataset(),
+            None => "production",
+        };
+        let sanity = SanityConfig::load(&mut settings, default_dataset);
+
+        let session_store =
+            settings.parsed("SESSION_STORE", "sessions.store", SessionStoreKind::Memory);
+        if session_store == SessionStoreKind::Postgrest
+            && supabase.as_ref().and_then(|s| s.service_role_key.as_ref()).is_none()
+        {
+            settings.problems.push(
+                "SUPABASE_SERVICE_ROLE_KEY is required when SESSION_STORE is `postgrest`"
+                    .to_string(),
+            );
+        }
+
+        let login_throttle = LoginThrottleConfig {
+            free_attempts: settings.parsed("LOGIN_FREE_ATTEMPTS", "login.free_attempts", 3),
+            base_lockout: Duration::from_secs(settings.parsed(
+                "LOGIN_BASE_LOCKOUT_SECS",
+                "login.base_lockout_secs",
+                2,
+            )),
+            max_lockout: Duration::from_secs(settings.parsed(
+                "LOGIN_MAX_LOCKOUT_SECS",
+                "login.max_lockout_secs",
+                15 * 60,
+            )),
+        };
+        // Entries are forgotten after `max_lockout`, so zero would disable the throttle
+        if login_throttle.max_lockout.is_zero()
+            || login_throttle.max_lockout < login_throttle.base_lockout
+        {
+            settings.problems.push(format!(
+                "LOGIN_MAX_LOCKOUT_SECS must be positive and at least LOGIN_BASE_LOCKOUT_SECS ({}), got {}",
+                login_throttle.base_lockout.as_secs(),
+                login_throttle.max_lockout.as_secs()
+            ));
+        }
+
+        if !settings.problems.is_empty() {
+            return Err(ConfigError(settings.problems));
+        }
+        Ok(AppConfig {
+            profile,
+            template_hot_reload,
+            detailed_errors,
+            secure_cookies,
+            log_format,
+            server,
+            supabase,
+            sanity,
+            session_store,
+            login_throttle,
+        })
+    }
+}
@@ -0,0 +1,399 @@
+/// Handlers for Supabase OAuth login using the PKCE flow, plus authentication
+/// extractors and authorization guards.
+use crate::configs::config::{AppConfig, SupabaseConfig};
+use crate::handlers::error::AppError;
+use crate::models::model::{
+    OAuthCallbackQuery, PkceTokenRequest, RefreshTokenRequest, SupabaseLoginResponse, SupabaseUser,
+};
+use crate::models::session::{Session, SessionStore, SessionStoreError, SESSION_COOKIE};
+use actix_web::body::EitherBody;
+use actix_web::cookie::time::Duration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::LOCATION;
+use actix_web::web::Data;
+use actix_web::{get, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::rc::Rc;
+
+/// Name of the short-lived cookie holding the PKCE code verifier.
+const PKCE_VERIFIER_COOKIE: &str = "pkce_verifier";
+
+/// OAuth providers that can be used to sign in through Supabase.
+const OAUTH_PROVIDERS: &[&str] = &[
+    "apple",
+    "azure",
+    "bitbucket",
+    "discord",
+    "facebook",
+    "figma",
+    "github",
+    "gitlab",
+    "google",
+    "kakao",
+    "keycloak",
+    "linkedin_oidc",
+    "notion",
+    "slack",
+    "spotify",
+    "twitch",
+    "twitter",
+    "workos",
+    "zoom",
+];
+
+/// Generates a random PKCE code verifier.
+///
+/// The verifier is 64 alphanumeric characters, well within the 43-128 range
+/// required by RFC 7636.
+fn generate_code_verifier() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(64).map(char::from).collect()
+}
+
+/// Derives the S256 code challenge for a code verifier.
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Returns whether cookies should be marked `Secure` in the current environment.
+fn secure_cookies(req: &HttpRequest) -> bool {
+    req.app_data::<Data<AppConfig>>().is_none_or(|config| config.secure_cookies)
+}
+
+/// Builds the absolute URL Supabase should redirect back to after the provider login.
+fn callback_url(req: &HttpRequest) -> String {
+    let conn = req.connection_info();
+    format!("{}://{}/auth/callback", conn.scheme(), conn.host())
+}
+
+/// Starts an OAuth login with the given provider.
+///
+/// Generates a PKCE code verifier, stores it in a short-lived cookie and
+/// redirects the browser to the Supabase authorize endpoint with the derived
+/// code challenge.
+#[get("/auth/{provider}")]
+pub async fn oauth_login(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    supabase: Data<SupabaseConfig>,
+) -> Result<HttpResponse, AppError> {
+    let provider = provider.into_inner();
+    if !OAUTH_PROVIDERS.contains(&provider.as_str()) {
+        return Err(AppError::LoginFailed(format!("Unsupported provider: {}", provider)));
+    }
+
+    let verifier = generate_code_verifier();
+    let authorize_url = reqwest::Url::parse_with_params(
+        &format!("{}/authorize", supabase.auth_url),
+        &[
+            ("provider", provider.as_str()),
+            ("redirect_to", callback_url(&req).as_str()),
+            ("code_challenge", code_challenge(&verifier).as_str()),
+            ("code_challenge_method", "s256"),
+        ],
+    )
+    .map_err(|e| AppError::upstream("Supabase", e))?;
+
+    // Lax is required so the cookie survives the top-level redirect back from the provider
+    let verifier_cookie = Cookie::build(PKCE_VERIFIER_COOKIE, verifier)
+        .path("/auth")
+        .http_only(true)
+        .secure(secure_cookies(&req))
+        .same_site(SameSite::Lax)
+        .max_age(Duration::minutes(10))
+        .finish();
+
+    Ok(HttpResponse::Found()
+        .cookie(verifier_cookie)
+        .insert_header((LOCATION, authorize_url.to_string()))
+        .finish())
+}
+
+/// Completes an OAuth login.
+///
+/// Exchanges the authorization code together with the stored PKCE code verifier
+/// for a Supabase session, sets the session cookies and redirects to the home page.
+#[get("/auth/callback")]
+pub async fn oauth_callback(
+    req: HttpRequest,
+    query: web::Query<OAuthCallbackQuery>,
+    store: Data<dyn SessionStore>,
+    supabase: Data<SupabaseConfig>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    if let Some(error) = query.error {
+        return Err(AppError::LoginFailed(query.error_description.unwrap_or(error)));
+    }
+    let auth_code = match query.code {
+        Some(code) => code,
+        None => return Err(AppError::LoginFailed("Missing authorization code".to_string())),
+    };
+    let code_verifier = match req.cookie(PKCE_VERIFIER_COOKIE) {
+        Some(cookie) => cookie.value().to_string(),
+        None => {
+            return Err(AppError::LoginFailed(
+                "Login session expired, please try again".to_string(),
+            ))
+        }
+    };
+
+    let response = Client::new()
+        .post(format!("{}/token?grant_type=pkce", supabase.auth_url))
+        .header("apikey", &supabase.public_key)
+        .json(&PkceTokenRequest { auth_code, code_verifier })
+        .send()
+        .await
+        .map_err(|e| AppError::upstream("Supabase", e))?;
+    if !response.status().is_success() {
+        return Err(AppError::LoginFailed("Invalid or expired authorization code".to_string()));
+    }
+    let supabase_res = response
+        .json::<SupabaseLoginResponse>()
+        .await
+        .map_err(|e| AppError::upstream("Supabase", e))?;
+
+    let session_cookie = start_session(store.as_ref(), &req, supabase_res).await?;
+
+    let mut clear_verifier_cookie = Cookie::build(PKCE_VERIFIER_COOKIE, "").path("/auth").finish();
+    clear_verifier_cookie.make_removal();
+
+    Ok(HttpResponse::SeeOther()
+        .cookie(session_cookie)
+        .cookie(clear_verifier_cookie)
+        .insert_header((LOCATION, "/"))
+        .finish())
+}
+
+/// Builds the cookie carrying an opaque session id.
+fn session_cookie(req: &HttpRequest, session_id: String) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, session_id)
+        .path("/")
+        .http_only(true)
+        .secure(secure_cookies(req))
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+/// Builds a cookie removing the session id from the browser.
+pub fn clear_session_cookie(req: &HttpRequest) -> Cookie<'static> {
+    let mut cookie = session_cookie(req, String::new());
+    cookie.make_removal();
+    cookie
+}
+
+/// Stores a server-side session for a successful Supabase login.
+///
+/// Returns the cookie carrying the opaque session id; the Supabase tokens never
+/// leave the server.
+pub async fn start_session(
+    store: &dyn SessionStore,
+    req: &HttpRequest,
+    login: SupabaseLoginResponse,
+) -> Result<Cookie<'static>, SessionStoreError> {
+    let user_agent =
+        req.headers().get("User-Agent").and_then(|value| value.to_str().ok()).map(str::to_string);
+    let session = Session::new(
+        login.user,
+        login.access_token,
+        login.refresh_token,
+        login.expires_at,
+        user_agent,
+    );
+    store.save(&session).await?;
+    Ok(session_cookie(req, session.id))
+}
+
+/// Ends a session.
+///
+/// Revokes the session's refresh token at Supabase and removes it from the store.
+/// A failure to reach Supabase does not prevent the local session from being removed.
+pub async fn end_session(
+    supabase: &SupabaseConfig,
+    store: &dyn SessionStore,
+    session: &Session,
+) -> Result<(), SessionStoreError> {
+    let _ = Client::new()
+        .post(format!("{}/logout?scope=local", supabase.auth_url))
+        .header("apikey", &supabase.public_key)
+        .bearer_auth(&session.access_token)
+        .send()
+        .await;
+    store.remove(&session.id).await
+}
+
+/// Exchanges the session's refresh token for a new access token.
+///
+/// Returns `None` when Supabase rejects the refresh token, e.g. because the
+/// session was signed out elsewhere.
+async fn refresh_session(supabase: &SupabaseConfig, mut session: Session) -> Option<Session> {
+    let response = Client::new()
+        .post(format!("{}/token?grant_type=refresh_token", supabase.auth_url))
+        .header("apikey", &supabase.public_key)
+        .json(&RefreshTokenRequest { refresh_token: session.refresh_token.clone() })
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let refreshed = response.json::<SupabaseLoginResponse>().await.ok()?;
+    session.access_token = refreshed.access_token;
+    session.refresh_token = refreshed.refresh_token;
+    session.expires_at = refreshed.expires_at;
+    session.user = refreshed.user;
+    Some(session)
+}
+
+/// The session resolved for a request, cached in the request extensions.
+#[derive(Clone)]
+struct ResolvedSession(Option<Session>);
+
+/// Loads the session referenced by the request's session cookie.
+///
+/// Sessions whose access token is about to expire are refreshed; sessions that can
+/// no longer be refreshed are removed from the store.
+async fn load_session(req: &HttpRequest) -> Option<Session> {
+    let session_id = req.cookie(SESSION_COOKIE)?;
+    let store = req.app_data::<Data<dyn SessionStore>>()?;
+    let supabase = req.app_data::<Data<SupabaseConfig>>()?;
+    let session = store.get(session_id.value()).await.ok()??;
+    if !session.needs_refresh() {
+        return Some(session);
+    }
+
+    match refresh_session(supabase, session.clone()).await {
+        Some(refreshed) => {
+            store.save(&refreshed).await.ok()?;
+            Some(refreshed)
+        }
+        None => {
+            let _ = store.remove(&session.id).await;
+            None
+        }
+    }
+}
+
+/// Resolves the session of the request.
+///
+/// The result is cached in the request extensions so guards, extractors and
+/// renders only hit the session store once per request.
+pub async fn resolve_session(req: &HttpRequest) -> Option<Session> {
+    if let Some(ResolvedSession(session)) = req.extensions().get::<ResolvedSession>() {
+        return session.clone();
+    }
+    let session = load_session(req).await;
+    req.extensions_mut().insert(ResolvedSession(session.clone()));
+    session
+}
+
+/// Resolves the authenticated Supabase user for a request.
+pub async fn resolve_user(req: &HttpRequest) -> Option<SupabaseUser> {
+    resolve_session(req).await.map(|session| session.user)
+}
+
+/// Extractor for the authenticated Supabase user.
+///
+/// Rejects the request with `AppError::Unauthorized` (an `HX-Redirect` to `/login`
+/// for htmx requests) when there is no valid session.
+pub struct AuthenticatedUser(pub SupabaseUser);
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            match resolve_user(&req).await {
+                Some(user) => Ok(AuthenticatedUser(user)),
+                None => Err(AppError::Unauthorized.into()),
+            }
+        })
+    }
+}
+
+/// Route guard restricting access to users matching a predicate.
+///
+/// Unauthenticated requests are rejected with `AppError::Unauthorized` and
+/// authenticated users failing the predicate with `AppError::Forbidden`; htmx
+/// requests receive an `HX-Redirect` to `/login` in both cases.
+///
+/// ```ignore
+/// #[get("/admin", wrap = "Authorize::role(\"admin\")")]
+/// ```
+pub struct Authorize {
+    predicate: Rc<dyn Fn(&SupabaseUser) -> bool>,
+}
+
+impl Authorize {
+    /// Creates a guard admitting users for which `predicate` returns true.
+    ///
+    /// Useful for checks on custom claims stored in `app_metadata`.
+    pub fn new(predicate: impl Fn(&SupabaseUser) -> bool + 'static) -> Self {
+        Authorize { predicate: Rc::new(predicate) }
+    }
+
+    /// Creates a guard admitting users with the given Supabase role.
+    pub fn role(role: &str) -> Self {
+        let role = role.to_string();
+        Authorize::new(move |user| user.role == role)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authorize
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthorizeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthorizeMiddleware {
+            service: Rc::new(service),
+            predicate: self.predicate.clone(),
+        }))
+    }
+}
+
+/// Middleware created by the `Authorize` guard.
+pub struct AuthorizeMiddleware<S> {
+    service: Rc<S>,
+    predicate: Rc<dyn Fn(&SupabaseUser) -> bool>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthorizeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let predicate = self.predicate.clone();
+        Box::pin(async move {
+            let error = match resolve_user(req.request()).await {
+                Some(user) if predicate(&user) => {
+                    return service.call(req).await.map(ServiceResponse::map_into_left_body)
+                }
+                Some(_) => AppError::Forbidden,
+                None => AppError::Unauthorized,
+            };
+            Ok(req.error_response(error).map_into_right_body())
+        })
+    }
+}
@@ -0,0 +1,249 @@
+/// The application error type and its request-aware responses.
+use crate::configs::config::AppConfig;
+use crate::handlers::render::{is_partial_request, render, user_context, RenderError, Template};
+use crate::models::groq::GroqError;
+use crate::models::model::{Navigation, TeraTemplates};
+use crate::models::session::SessionStoreError;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::StatusCode;
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use std::error::Error;
+use std::fmt;
+use tera::Context;
+
+/// CSS selector of the element in `index.html` that shows errors of htmx requests.
+const ERRORS_TARGET: &str = "#errors";
+
+/// An error raised by a handler.
+///
+/// `ResponseError` only yields a plain text response. The `error_handlers` middleware
+/// replaces it with JSON for `/api/*`, a fragment swapped into the global error area
+/// for htmx requests, and the fragment within the `error.html` page for normal
+/// navigation. The fragment is rendered from `<status>.html` (e.g. `404.html`) when
+/// such a template exists. htmx requests failing authentication or authorization
+/// receive an `HX-Redirect` to the login page instead.
+#[derive(Debug)]
+pub enum AppError {
+    /// A template failed to render.
+    Render(RenderError),
+    /// The session store failed.
+    Session(SessionStoreError),
+    /// A backing service such as Supabase or Sanity failed or returned unusable data.
+    Upstream { service: &'static str, message: String },
+    /// A CMS query built from request input is invalid.
+    InvalidQuery(GroqError),
+    /// Request parameters are malformed or out of range.
+    BadRequest(String),
+    /// An OAuth login was rejected or could not be completed.
+    LoginFailed(String),
+    /// The request needs a logged-in user.
+    Unauthorized,
+    /// The logged-in user may not access the route.
+    Forbidden,
+    /// No route or content matches the path.
+    NotFound,
+    /// A route matches the path, but not the request method.
+    MethodNotAllowed,
+}
+
+impl AppError {
+    /// Creates an `Upstream` error for `service` from any displayable error.
+    pub fn upstream(service: &'static str, error: impl fmt::Display) -> Self {
+        AppError::Upstream { service, message: error.to_string() }
+    }
+
+    /// Returns the message that is safe to show to users.
+    pub fn public_message(&self) -> String {
+        match self {
+            AppError::Render(_) | AppError::Session(_) => "Something went wrong".to_string(),
+            AppError::Upstream { service, .. } => format!("{} is unavailable right now", service),
+            AppError::InvalidQuery(e) => format!("Invalid query: {}", e),
+            AppError::BadRequest(message) => format!("Bad request: {}", message),
+            AppError::LoginFailed(message) => format!("Login failed: {}", message),
+            AppError::Unauthorized => "Please log in to continue".to_string(),
+            AppError::Forbidden => "You do not have access to this page".to_string(),
+            AppError::NotFound => "Page not found".to_string(),
+            AppError::MethodNotAllowed => "Method not allowed".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Render(e) => write!(f, "{}", e),
+            AppError::Session(e) => write!(f, "{}", e),
+            AppError::Upstream { service, message } => {
+                write!(f, "{} request failed: {}", service, message)
+            }
+            AppError::InvalidQuery(e) => write!(f, "invalid query: {}", e),
+            AppError::BadRequest(message) => write!(f, "bad request: {}", message),
+            AppError::LoginFailed(message) => write!(f, "login failed: {}", message),
+            AppError::Unauthorized => write!(f, "no valid session"),
+            AppError::Forbidden => write!(f, "the user is not authorized for the route"),
+            AppError::NotFound => write!(f, "no route or content matches the path"),
+            AppError::MethodNotAllowed => write!(f, "no route matches the method"),
+        }
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::Render(e) => Some(e),
+            AppError::Session(e) => Some(e),
+            AppError::InvalidQuery(e) => Some(e),
+            AppError::Upstream { .. }
+            | AppError::BadRequest(_)
+            | AppError::LoginFailed(_)
+            | AppError::Unauthorized
+            | AppError::Forbidden
+            | AppError::NotFound
+            | AppError::MethodNotAllowed => None,
+        }
+    }
+}
+
+impl From<RenderError> for AppError {
+    fn from(error: RenderError) -> Self {
+        AppError::Render(error)
+    }
+}
+
+impl From<GroqError> for AppError {
+    fn from(error: GroqError) -> Self {
+        AppError::InvalidQuery(error)
+    }
+}
+
+impl From<SessionStoreError> for AppError {
+    fn from(error: SessionStoreError) -> Self {
+        AppError::Session(error)
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Render(_) | AppError::Session(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Upstream { .. } => StatusCode::BAD_GATEWAY,
+            AppError::InvalidQuery(_) | AppError::BadRequest(_) | AppError::LoginFailed(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.public_message())
+    }
+}
+
+/// Creates the middleware that renders `AppError` responses for the kind of request.
+///
+/// Other error responses pass through unchanged.
+pub fn error_handlers<B: 'static>() -> ErrorHandlers<B> {
+    ErrorHandlers::new().default_handler(respond_to_app_error)
+}
+
+/// Logs an `AppError` and replaces its response with one suited to the request.
+fn respond_to_app_error<B: 'static>(
+    res: ServiceResponse<B>,
+) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let Some(error) = res.response().error().and_then(|e| e.as_error::<AppError>()) else {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    };
+
+    let status = res.status();
+    if status.is_server_error() {
+        log::error!("{} {}: {}", res.request().method(), res.request().path(), error);
+    } else {
+        log::info!("{} {}: {}", res.request().method(), res.request().path(), error);
+    }
+    let detailed =
+        res.request().app_data::<Data<AppConfig>>().is_some_and(|config| config.detailed_errors);
+    let message = error.public_message();
+    let detail = detailed.then(|| error.to_string());
+
+    let (req, _) = res.into_parts();
+    Ok(ErrorHandlerResponse::Future(Box::pin(async move {
+        let response = error_response(&req, status, &message, detail.as_deref()).await;
+        Ok(ServiceResponse::new(req, response).map_into_right_body())
+    })))
+}
+
+/// Builds the error response for `req`.
+///
+/// `detail` holds the internal error description, shown only with detailed errors enabled.
+async fn error_response(
+    req: &HttpRequest,
+    status: StatusCode,
+    message: &str,
+    detail: Option<&str>,
+) -> HttpResponse {
+    // The whole page navigates to the login page instead of swapping the error in
+    if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        && req.headers().contains_key("HX-Request")
+    {
+        return HttpResponse::build(status)
+            .insert_header(("HX-Redirect", "/login"))
+            .body(message.to_string());
+    }
+    if req.path().starts_with("/api/") {
+        return HttpResponse::build(status)
+            .json(serde_json::json!({ "error": message, "detail": detail }));
+    }
+
+    let mut context = user_context(req).await;
+    context.insert("navigation", &Navigation::new("error"));
+    context.insert("status", &status.as_u16());
+    context.insert("reason", &status.canonical_reason().unwrap_or_default());
+    context.insert("message", message);
+    context.insert("detail", &detail);
+    context.insert("method", req.method().as_str());
+    context.insert("path", req.path());
+    let templates = req.app_data::<Data<TeraTemplates>>();
+
+    let fragment = templates
+        .zip(Template::for_status(status))
+        .and_then(|(tera, template)| render_optional(tera, template, &context))
+        .unwrap_or_else(|| {
+            format!(
+                "<div class=\"error\" role=\"alert\"><h1>{} {}</h1><p>{}</p>{}</div>",
+                status.as_u16(),
+                status.canonical_reason().unwrap_or_default(),
+                tera::escape_html(message),
+                detail.map(|d| format!("<pre>{}</pre>", tera::escape_html(d))).unwrap_or_default()
+            )
+        });
+    if is_partial_request(req) {
+        return HttpResponse::build(status)
+            .insert_header(("HX-Retarget", ERRORS_TARGET))
+            .insert_header(("HX-Reswap", "innerHTML"))
+            .content_type("text/html; charset=utf-8")
+            .body(fragment);
+    }
+
+    context.insert("fragment", &fragment);
+    let page = templates.and_then(|tera| render_optional(tera, Template::Error, &context));
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(page.unwrap_or(fragment))
+}
+
+/// Renders `template`, returning `None` when it does not exist or fails to render.
+fn render_optional(tera: &TeraTemplates, template: Template, context: &Context) -> Option<String> {
+    match render(tera, template, context) {
+        Ok(rendered) => Some(rendered),
+        Err(e) if matches!(e.source.kind, tera::ErrorKind::TemplateNotFound(_)) => None,
+        Err(e) => {
+            log::error!("{}", e);
+            None
+        }
+    }
+}
@@ -1,51 +1,175 @@
 /// Handlers for various web endpoints in the application.
 use crate::actors::actor::ChatSocket;
+use crate::configs::config::{AppConfig, SupabaseConfig};
+use crate::handlers::auth::{
+    clear_session_cookie, end_session, oauth_callback, oauth_login, resolve_session, start_session,
+    AuthenticatedUser, Authorize,
+};
+use crate::handlers::error::AppError;
+use crate::handlers::render::{
+    page_or_fragment_context, render, render_page_or_fragment, render_template, user_context,
+    Template,
+};
+use crate::models::events::ContentEvents;
+use crate::models::groq::{Direction, GroqQuery, Op};
 use crate::models::model::{
-    Counter, Item, LoginRequest, MySanityConfig, Navigation, SupabaseLoginResponse, TeraTemplates,
+    Counter, Item, ItemListQuery, LoginRequest, Navigation, PageCursor, SupabaseLoginResponse,
+    TeraTemplates,
 };
-use actix_web::http::header::CACHE_CONTROL;
+use crate::models::reload::TemplateReloader;
+use crate::models::sanity::{decode_documents, SanityClient};
+use crate::models::session::{SessionStore, SessionSummary};
+use crate::models::shutdown::ShutdownCoordinator;
+use crate::models::throttle::LoginThrottle;
+use actix_web::http::header::{CACHE_CONTROL, LINK, LOCATION, RETRY_AFTER};
 use actix_web::web;
 use actix_web::{
     get, post,
     web::{Bytes, Data},
     Error, HttpRequest, HttpResponse, Responder,
 };
-use futures::stream::StreamExt;
+use futures::stream::{self, StreamExt};
 use reqwest::Client;
-use tera::Context;
 
-use actix_web::cookie::time::Duration;
 use actix_web::cookie::Cookie;
 use actix_web_actors::ws;
 use postgrest::Postgrest;
-use sanity::helpers::get_json;
-use serde_json::{from_value, Value};
+use serde_json::Value;
+use std::time::Duration;
+use tera::Context;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::time::interval;
 use tokio_stream::wrappers::IntervalStream;
 
+/// Paths served by `supabase_routes`.
+const SUPABASE_PATHS: &[&str] = &[
+    "/login",
+    "/logout",
+    "/profile",
+    "/sessions/{handle}/revoke",
+    "/auth/callback",
+    "/auth/{provider}",
+    "/api/leaderboard",
+];
+
+/// Paths served by `sanity_routes`.
+const SANITY_PATHS: &[&str] =
+    &["/content", "/content/{slug}", "/api/sanity", "/api/sanity/events", "/webhooks/sanity"];
+
+/// Maximum number of items listed on the content page.
+const LISTED_ITEMS: usize = 100;
+
+/// Number of items per page of `/api/sanity` when no `size` is given.
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Largest `size` accepted by `/api/sanity`.
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Fields `/api/sanity` can sort by. Documents without the sort field are left out,
+/// so the cursor never has to continue from a null value.
+const SORT_FIELDS: &[&str] = &["_updatedAt", "_createdAt", "_id", "name"];
+
+/// Registers the routes backed by Supabase.
+///
+/// Expects `SupabaseConfig`, `Postgrest` and `SessionStore` application data.
+pub fn supabase_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(login)
+        .service(login_page)
+        .service(logout)
+        .service(profile)
+        .service(revoke_session)
+        .service(oauth_callback)
+        .service(oauth_login)
+        .service(get_leaderboard);
+}
+
+/// Registers the routes backed by Sanity.
+///
+/// Expects `SanityClient` and `ContentEvents` application data.
+pub fn sanity_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(content).service(content_item).service(get_content).service(content_events);
+}
+
+/// Registers placeholders for the routes of Supabase, answering with a 503 fragment.
+pub fn supabase_unavailable_routes(cfg: &mut web::ServiceConfig) {
+    unavailable_routes(cfg, "Supabase", SUPABASE_PATHS);
+}
+
+/// Registers placeholders for the routes of Sanity, answering with a 503 fragment.
+pub fn sanity_unavailable_routes(cfg: &mut web::ServiceConfig) {
+    unavailable_routes(cfg, "Sanity", SANITY_PATHS);
+}
+
+/// Registers `paths` to answer every request with a 503 fragment naming the disabled integration.
+fn unavailable_routes(cfg: &mut web::ServiceConfig, integration: &'static str, paths: &[&str]) {
+    for path in paths {
+        cfg.service(web::resource(*path).to(move || integration_unavailable(integration)));
+    }
+}
+
+/// Explains that a feature is unavailable because its integration is not configured.
+async fn integration_unavailable(integration: &str) -> HttpResponse {
+    HttpResponse::ServiceUnavailable().body(format!(
+        "
+        <div id=\"unavailable\">
+            <h1>{} is not configured</h1>
+            <p>This feature is disabled on this server.</p>
+        </div>
+        ",
+        integration
+    ))
+}
+
+/// Responds to requests that no route matches.
+///
+/// Answers 405 when a route exists for the path but not for the request method, and
+/// 404 otherwise. The response is rendered by the `error_handlers` middleware.
+pub async fn no_route(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    if req.resource_map().has_resource(req.path()) {
+        Err(AppError::MethodNotAllowed)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Redirects a plain HTTP request to the same URL over HTTPS.
+///
+/// Serves every request of the HTTP listener when TLS is enabled.
+pub async fn redirect_to_https(req: HttpRequest, config: Data<AppConfig>) -> impl Responder {
+    let https_port = config.server.tls.as_ref().map_or(443, |tls| tls.https_port);
+    let conn = req.connection_info();
+    let host = match conn.host().rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => conn.host(),
+    };
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let location = match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+    HttpResponse::PermanentRedirect().insert_header((LOCATION, location)).finish()
+}
+
 /// Logs out the current user.
 ///
-/// Clears the authentication cookies, effectively logging out the user.
-/// Returns an HTML form for logging back in.
+/// Signs the session out at Supabase, removes it from the session store and clears
+/// the session cookie. Returns an HTML form for logging back in.
 #[post("/logout")]
-pub async fn logout() -> impl Responder {
-    // Create a cookie to clear the authentication
-    let clear_cookie_access_token = Cookie::build("access_token", "")
-        .http_only(true)
-        .secure(true)
-        .max_age(Duration::minutes(0)) // Set max-age to zero
-        .finish();
-
-    let clear_cookie_refresh_token = Cookie::build("refresh_token", "")
-        .http_only(true)
-        .secure(true)
-        .max_age(Duration::minutes(0)) // Set max-age to zero
-        .finish();
+pub async fn logout(
+    req: HttpRequest,
+    store: Data<dyn SessionStore>,
+    supabase: Data<SupabaseConfig>,
+) -> impl Responder {
+    if let Some(session) = resolve_session(&req).await {
+        if let Err(e) = end_session(&supabase, store.as_ref(), &session).await {
+            log::warn!("Failed to end session: {}", e);
+        }
+    }
+
     // Return the login form HTML
     HttpResponse::Ok()
-        .cookie(clear_cookie_access_token) // Set the cookie in the response to clear it
-        .cookie(clear_cookie_refresh_token) // Set the cookie in the response to clear it
-        .body(format!(
+        .cookie(clear_session_cookie(&req)) // Set the cookie in the response to clear it
+        .body(
             "
             <form hx-boost=\"true\" id=\"form\" hx-post=\"/login\">
               <input type=\"text\" name=\"email\" placeholder=\"email\" />
@@ -53,81 +177,157 @@ pub async fn logout() -> impl Responder {
               <button type=\"submit\">Login</button>
               <h1>Logged out</h1>
             </form>
-            "
-        ))
+            ",
+        )
+}
+
+/// Returns the login form with an "Invalid credentials" message.
+///
+/// When the client is locked out, the message tells it how long to wait and a
+/// `Retry-After` header is set.
+fn invalid_credentials(retry_after: Option<Duration>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    let retry_message = match retry_after {
+        Some(retry_after) => {
+            let seconds = retry_after.as_secs().max(1);
+            response.insert_header((RETRY_AFTER, seconds));
+            format!("<p>Too many failed attempts. Try again in {} seconds.</p>", seconds)
+        }
+        None => String::new(),
+    };
+    response.body(format!(
+        "
+        <form hx-boost=\"true\" id=\"form\" hx-post=\"/login\">
+            <input type=\"text\" name=\"email\" value=\"\" placeholder=\"email\" />
+            <input type=\"password\" name=\"password\" value=\"\" placeholder=\"password\" />
+            <button type=\"submit\">Login</button>
+            <h1>Invalid credentials</h1>
+            {}
+        </form>
+        ",
+        retry_message
+    ))
 }
 
 /// Authenticates a user and establishes a session.
 ///
 /// Expects a `LoginRequest` containing email and password.
-/// If authentication is successful, sets cookies and returns a user-specific greeting.
-/// Otherwise, it returns a form with an error message.
+/// If authentication is successful, starts a server-side session, sets the session
+/// cookie and returns a user-specific greeting.
+/// Otherwise, it returns a form with an error message. Repeated failures for the
+/// same email or client IP lock further attempts out for an increasing period.
 #[post("/login")]
-pub async fn login(credentials: web::Form<LoginRequest>) -> impl Responder {
+pub async fn login(
+    req: HttpRequest,
+    credentials: web::Form<LoginRequest>,
+    store: Data<dyn SessionStore>,
+    throttle: Data<LoginThrottle>,
+    supabase: Data<SupabaseConfig>,
+) -> Result<HttpResponse, AppError> {
     let client = Client::new();
 
-    let creds_json = match serde_json::to_string(&credentials.into_inner()) {
-        Ok(json) => json,
-        Err(_) => return HttpResponse::InternalServerError().json("Error serializing credentials"),
-    };
-
-    // Retrieve the SUPABASE_PUBLIC_KEY from environment
-    let supabase_public_key = match std::env::var("SUPABASE_PUBLIC_KEY") {
-        Ok(key) => key,
-        Err(_) => return HttpResponse::InternalServerError().json("SUPABASE_PUBLIC_KEY not set"),
-    };
+    let email_key = format!("email:{}", credentials.email.trim().to_lowercase());
+    let ip_key =
+        format!("ip:{}", req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default());
+    let throttle_keys = [email_key.clone(), ip_key];
+    if let Some(retry_after) = throttle.retry_after(&throttle_keys) {
+        return Ok(invalid_credentials(Some(retry_after)));
+    }
 
-    let res = client
-        .post("https://kxbzixfkcjexfwfacnzq.supabase.co/auth/v1/token?grant_type=password")
-        .header("apikey", &supabase_public_key)
-        .header("Content-Type", "application/json")
-        .body(creds_json)
+    let response = client
+        .post(format!("{}/token?grant_type=password", supabase.auth_url))
+        .header("apikey", &supabase.public_key)
+        .json(&credentials.into_inner())
         .send()
-        .await;
-
-    match res {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<SupabaseLoginResponse>().await {
-                    Ok(supabase_res) => {
-                        let cookie_access_token =
-                            Cookie::new("access_token", supabase_res.access_token);
-                        let cookie_refresh_token =
-                            Cookie::new("refresh_token", supabase_res.refresh_token);
-
-                        dbg!(cookie_access_token.clone());
-                        dbg!(supabase_res.user.email.clone());
-                        HttpResponse::Ok()
-                            .cookie(cookie_access_token)
-                            .cookie(cookie_refresh_token)
-                            .body(format!(
-                                "
-                                <form hx-boost=\"true\" id=\"form\" hx-post=\"/logout\">
-                                    <button type=\"submit\">Logout</button>
-                                <h1>Logged in as {}</h1>
-                                </form>
-                                ",
-                                supabase_res.user.email
-                            ))
-                    }
-                    Err(_) => HttpResponse::InternalServerError().finish(),
-                }
-            } else {
-                dbg!(response.status().is_success());
-                HttpResponse::Ok().body(format!(
-                    "
-                    <form hx-boost=\"true\" id=\"form\" hx-post=\"/login\">
-                        <input type=\"text\" name=\"email\" value=\"\" placeholder=\"email\" />
-                        <input type=\"password\" name=\"password\" value=\"\" placeholder=\"password\" />
-                        <button type=\"submit\">Login</button>
-                        <h1>Invalid credentials</h1>
-                    </form>
-                    "
-                ))
-            }
+        .await
+        .map_err(|e| AppError::upstream("Supabase", e))?;
+
+    if !response.status().is_success() {
+        let locked = throttle.record_failure(&throttle_keys);
+        for (key, lockout) in &locked {
+            log::warn!(
+                target: "audit",
+                "login lockout key={} duration_secs={}",
+                key,
+                lockout.as_secs()
+            );
         }
-        Err(_) => HttpResponse::InternalServerError().finish(),
+        return Ok(invalid_credentials(locked.into_iter().map(|(_, lockout)| lockout).max()));
     }
+
+    let supabase_res = response
+        .json::<SupabaseLoginResponse>()
+        .await
+        .map_err(|e| AppError::upstream("Supabase", e))?;
+    throttle.record_success(&[email_key]);
+    let email = supabase_res.user.email.clone();
+    let session_cookie = start_session(store.as_ref(), &req, supabase_res).await?;
+
+    Ok(HttpResponse::Ok().cookie(session_cookie).body(format!(
+        "
+            <form hx-boost=\"true\" id=\"form\" hx-post=\"/logout\">
+                <button type=\"submit\">Logout</button>
+            <h1>Logged in as {}</h1>
+            </form>
+            ",
+        email
+    )))
+}
+
+/// Displays the login page.
+///
+/// Renders the login form on its own page. Guarded routes redirect htmx requests here
+/// when the user is not authenticated.
+#[get("/login")]
+pub async fn login_page(
+    req: HttpRequest,
+    tera: Data<TeraTemplates>,
+) -> Result<HttpResponse, AppError> {
+    render_template(&req, &tera, "login", Template::Login).await
+}
+
+/// Displays the profile page of the authenticated user.
+///
+/// Lists the user's active sessions so they can be revoked.
+/// Only users with the Supabase `authenticated` role can access this page.
+#[get("/profile", wrap = "Authorize::role(\"authenticated\")")]
+pub async fn profile(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    store: Data<dyn SessionStore>,
+    tera: Data<TeraTemplates>,
+) -> Result<HttpResponse, AppError> {
+    let AuthenticatedUser(user) = user;
+    let current_id = resolve_session(&req).await.map(|session| session.id).unwrap_or_default();
+    let sessions = store.list_for_user(&user.id).await?;
+    let sessions: Vec<SessionSummary> =
+        sessions.iter().map(|session| SessionSummary::new(session, &current_id)).collect();
+
+    let mut context = user_context(&req).await;
+    context.insert("navigation", &Navigation::new("profile"));
+    context.insert("user", &user);
+    context.insert("sessions", &sessions);
+    Ok(HttpResponse::Ok().body(render(&tera, Template::Profile, &context)?))
+}
+
+/// Revokes one of the authenticated user's sessions, identified by its handle.
+///
+/// Signs the session out at Supabase and removes it from the session store.
+/// Returns an empty body so htmx removes the session from the list.
+#[post("/sessions/{handle}/revoke", wrap = "Authorize::role(\"authenticated\")")]
+pub async fn revoke_session(
+    user: AuthenticatedUser,
+    handle: web::Path<String>,
+    store: Data<dyn SessionStore>,
+    supabase: Data<SupabaseConfig>,
+) -> Result<HttpResponse, AppError> {
+    let AuthenticatedUser(user) = user;
+    let sessions = store.list_for_user(&user.id).await?;
+    let Some(session) = sessions.iter().find(|session| session.handle() == *handle) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    end_session(&supabase, store.as_ref(), session).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Retrieves the leaderboard data.
@@ -135,39 +335,35 @@ pub async fn login(credentials: web::Form<LoginRequest>) -> impl Responder {
 /// Fetches leaderboard data from a Postgrest database and returns it as JSON.
 /// This endpoint requires a valid Postgrest client in the application state.
 #[get("/api/leaderboard")]
-pub async fn get_leaderboard(sb: Data<Postgrest>) -> impl Responder {
-    let resp = sb.from("leaderboard").select("*").execute().await.unwrap();
-    let body = resp.text().await.unwrap();
-    HttpResponse::Ok().json(body.parse::<Value>().unwrap())
+pub async fn get_leaderboard(sb: Data<Postgrest>) -> Result<HttpResponse, AppError> {
+    let resp = sb
+        .from("leaderboard")
+        .select("*")
+        .execute()
+        .await
+        .map_err(|e| AppError::upstream("Supabase", e))?;
+    let leaderboard = resp.json::<Value>().await.map_err(|e| AppError::upstream("Supabase", e))?;
+    Ok(HttpResponse::Ok().json(leaderboard))
 }
 
 /// Establishes a WebSocket connection for real-time communication.
 ///
 /// Initializes a WebSocket session using `ChatSocket` actor for bi-directional communication.
 #[get("/ws/")]
-pub async fn ws_index(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-    ws::start(ChatSocket {}, &req, stream)
-}
-
-/// Renders a specified template with navigation context.
-///
-/// Renders a template using Tera templating engine and includes navigation context based on the provided page.
-async fn render_template(tera: &Data<TeraTemplates>, page: &str, template: &str) -> impl Responder {
-    let navigation = Navigation::new(page);
-    let mut context = Context::new();
-    context.insert(String::from("navigation"), &navigation);
-    match tera.tera.render(template, &context) {
-        Ok(rendered) => HttpResponse::Ok().body(rendered),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+pub async fn ws_index(
+    req: HttpRequest,
+    stream: web::Payload,
+    shutdown: Data<ShutdownCoordinator>,
+) -> Result<HttpResponse, Error> {
+    ws::start(ChatSocket::new(shutdown.subscribe()), &req, stream)
 }
 
 /// Displays the home page.
 ///
 /// Renders the home page using the Tera templating engine.
 #[get("/")]
-pub async fn index(tera: Data<TeraTemplates>) -> impl Responder {
-    render_template(&tera, "home", "home.html").await
+pub async fn index(req: HttpRequest, tera: Data<TeraTemplates>) -> Result<HttpResponse, AppError> {
+    render_template(&req, &tera, "home", Template::Home).await
 }
 
 /// Renders the drag and drop component.
@@ -175,34 +371,17 @@ pub async fn index(tera: Data<TeraTemplates>) -> impl Responder {
 /// This function renders a static content page using Tera templating engine.
 /// It's an example of how to render a simple HTML page with context.
 #[get("/draganddrop")]
-pub async fn draganddrop(tera: Data<TeraTemplates>) -> impl Responder {
-    let context = Context::new();
-
-    let rendered = tera
-        .tera
-        .render("components/draganddrop.html", &context)
-        .expect("Failed to render template.");
-    HttpResponse::Ok().body(rendered)
+pub async fn draganddrop(tera: Data<TeraTemplates>) -> Result<HttpResponse, AppError> {
+    let rendered = render(&tera, Template::DragAndDrop, &Context::new())?;
+    Ok(HttpResponse::Ok().body(rendered))
 }
 
 /// Displays the about page.
 ///
 /// Renders the about page using the Tera templating engine.
 #[get("/about")]
-pub async fn about(tera: Data<TeraTemplates>) -> impl Responder {
-    render_template(&tera, "about", "about.html").await
-}
-
-/// Renders the content page.
-///
-/// This function renders a static content page using Tera templating engine.
-/// It's an example of how to render a simple HTML page with context.
-#[get("/content")]
-pub async fn content(tera: Data<TeraTemplates>) -> impl Responder {
-    let context = Context::new();
-
-    let rendered = tera.tera.render("content.html", &context).expect("Failed to render template.");
-    HttpResponse::Ok().body(rendered)
+pub async fn about(req: HttpRequest, tera: Data<TeraTemplates>) -> Result<HttpResponse, AppError> {
+    render_template(&req, &tera, "about", Template::About).await
 }
 
 /// Increments a counter and displays it on a webpage.
@@ -210,20 +389,24 @@ pub async fn content(tera: Data<TeraTemplates>) -> impl Responder {
 /// This function demonstrates how to use shared state (in this case, a counter)
 /// across requests. It increments the counter and renders it using Tera templates.
 #[get("/increment")]
-pub async fn get_comp(counter: Data<Counter>, tera: Data<TeraTemplates>) -> impl Responder {
+pub async fn get_comp(
+    counter: Data<Counter>,
+    tera: Data<TeraTemplates>,
+) -> Result<HttpResponse, AppError> {
     let name = "Increment-Andrey";
     let last_name = "Kowalski";
+    let mut context = Context::new();
+
     let mut counter = counter.count.lock().await;
     *counter += 1;
 
-    let mut context = Context::new();
     context.insert("name", &name);
     context.insert("last_name", &last_name);
     context.insert("counter", &*counter);
 
-    let rendered = tera.tera.render("comp.html", &context).expect("Failed to render template.");
+    let rendered = render(&tera, Template::Counter, &context)?;
 
-    HttpResponse::Ok().body(rendered)
+    Ok(HttpResponse::Ok().body(rendered))
 }
 
 /// Sets a cookie and displays the cookie's value on a webpage.
@@ -231,7 +414,7 @@ pub async fn get_comp(counter: Data<Counter>, tera: Data<TeraTemplates>) -> impl
 /// This function demonstrates cookie handling in Actix-web. It increments a value
 /// in a cookie on each request and displays this value using Tera templates.
 #[get("/cookie")]
-pub async fn cookie(req: HttpRequest, tera: Data<TeraTemplates>) -> impl Responder {
+pub async fn cookie(req: HttpRequest, tera: Data<TeraTemplates>) -> Result<HttpResponse, AppError> {
     let counter = if let Some(cookie) = req.cookie("counter") {
         cookie.value().parse::<i32>().unwrap_or(0) + 1
     } else {
@@ -247,9 +430,8 @@ pub async fn cookie(req: HttpRequest, tera: Data<TeraTemplates>) -> impl Respond
     context.insert("last_name", "Kowalski");
     context.insert("user_counter", &counter.to_string());
 
-    let rendered =
-        tera.tera.render("comp-user.html", &context).expect("Failed to render template.");
-    response.body(rendered)
+    let rendered = render(&tera, Template::CookieCounter, &context)?;
+    Ok(response.body(rendered))
 }
 
 /// Greets a user with their name.
@@ -264,12 +446,19 @@ pub async fn hello(name: web::Path<String>) -> impl Responder {
 /// Provides a server-sent events stream.
 ///
 /// This function demonstrates how to implement server-sent events (SSE) in Actix-web.
-/// It sends a simple event every second.
+/// It sends a simple event every second. On shutdown the stream ends with a `reconnect`
+/// event and a short `retry` so the client reconnects to another instance.
 #[get("/events")]
-pub async fn events() -> impl Responder {
+pub async fn events(shutdown: Data<ShutdownCoordinator>) -> impl Responder {
+    let shutdown = shutdown.subscribe();
     let server_sent_event = move || {
         let interval = IntervalStream::new(interval(std::time::Duration::from_secs(1)));
-        interval.map(move |_| Ok::<_, Error>(Bytes::from("id:1\ndata: Server-sent event \n\n")))
+        interval
+            .map(move |_| Ok::<_, Error>(Bytes::from("id:1\ndata: Server-sent event \n\n")))
+            .take_until(shutdown.recv())
+            .chain(stream::once(async {
+                Ok(Bytes::from("retry: 1000\nevent: reconnect\ndata: Server is shutting down\n\n"))
+            }))
     };
 
     HttpResponse::Ok()
@@ -278,62 +467,252 @@ pub async fn events() -> impl Responder {
         .streaming(server_sent_event())
 }
 
-/// Fetches content from Sanity CMS.
+/// Notifies open pages that templates were reloaded.
 ///
-/// Retrieves items using a GROQ query from the Sanity CMS and returns them as a JSON array.
-/// This requires a valid Sanity configuration in the application state.
-#[get("/api/sanity")]
-pub async fn get_content(sn: Data<MySanityConfig>) -> impl Responder {
-    let mut res = sn.sanity_config.lock().await;
-    let items = res.get(&String::from("*[_type == 'item']"));
-    let mut my_items: Vec<Item> = Vec::<Item>::new();
-    match items {
-        Ok(response) => {
-            let parsed = get_json(response);
-            match parsed {
-                Ok(Value::Object(obj)) => {
-                    if let Some(Value::Array(items_value)) = obj.get("result") {
-                        for item_value in items_value {
-                            // Deserialize each item in the array to an `Item`
-                            match from_value::<Item>(item_value.clone()) {
-                                Ok(item) => {
-                                    // println!("{:?}", item.name);
-                                    my_items.push(item)
-                                }
-                                Err(e) => {
-                                    println!("Failed to deserialize item: {:?}", e);
-                                    return HttpResponse::InternalServerError().body(e.to_string());
-                                }
-                            }
-                        }
-                    } else {
-                        println!("Result field is not an array or not present");
-                        return HttpResponse::InternalServerError().finish();
-                    }
-                }
-                _ => {
-                    println!("Failed to parse JSON or not an object at top level");
-                    return HttpResponse::InternalServerError().finish();
+/// Only registered with template hot reload enabled. Sends a `reload` event after
+/// every successful reload; the base template then reloads the page.
+#[get("/dev/reload")]
+pub async fn template_reloads(
+    reloader: Data<TemplateReloader>,
+    shutdown: Data<ShutdownCoordinator>,
+) -> impl Responder {
+    let reloads = stream::unfold(reloader.subscribe(), |mut reloads| async move {
+        reloads.changed().await.ok()?;
+        Some((Ok::<_, Error>(Bytes::from("event: reload\ndata: \n\n")), reloads))
+    });
+
+    HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(reloads.take_until(shutdown.subscribe().recv()))
+}
+
+/// Notifies open pages that CMS content changed.
+///
+/// Sends a `content` event with the operation, type and id of every document
+/// reported by the Sanity webhook. The base template turns it into a
+/// `content-changed` event on the body, so elements can re-fetch with
+/// `hx-trigger="content-changed from:body"`.
+#[get("/api/sanity/events")]
+pub async fn content_events(
+    content_changes: Data<ContentEvents>,
+    shutdown: Data<ShutdownCoordinator>,
+) -> impl Responder {
+    let changes = stream::unfold(content_changes.subscribe(), |mut changes| async move {
+        loop {
+            match changes.recv().await {
+                Ok(change) => {
+                    let data = serde_json::to_string(&change).unwrap_or_default();
+                    let event = Bytes::from(format!("event: content\ndata: {}\n\n", data));
+                    return Some((Ok::<_, Error>(event), changes));
                 }
+                // Missed changes are covered by the ones that follow
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
             }
         }
-        Err(e) => {
-            println!("Error fetching data: {:?}", e);
-            return HttpResponse::InternalServerError().body(e.to_string());
+    });
+
+    HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(changes.take_until(shutdown.subscribe().recv()))
+}
+
+/// Lists the active items from Sanity, newest first.
+///
+/// htmx navigation from an item page swaps in just the list.
+#[get("/content")]
+pub async fn content(
+    req: HttpRequest,
+    tera: Data<TeraTemplates>,
+    sanity: Data<SanityClient>,
+) -> Result<HttpResponse, AppError> {
+    let (query, params) = GroqQuery::of_type("item")
+        .filter("active", Op::Eq, true)
+        .order("_createdAt", Direction::Desc)
+        .slice(0, LISTED_ITEMS)
+        .build()?;
+    // Invalid items are logged and left out
+    let (items, _): (Vec<Item>, _) = sanity
+        .query_documents(&query, &params)
+        .await
+        .map_err(|e| AppError::upstream("Sanity", e))?;
+
+    let mut context = page_or_fragment_context(&req).await;
+    context.insert("navigation", &Navigation::new("content"));
+    context.insert("items", &items);
+    render_page_or_fragment(&req, &tera, Template::Content, Template::ContentListFragment, &context)
+}
+
+/// Shows the active item whose `slug.current` is `slug`.
+///
+/// htmx navigation from the list swaps in just the item. When the item refreshes
+/// itself after a content change and is gone (deleted, deactivated, renamed or no
+/// longer a valid item), htmx is sent back to the list with `HX-Location` instead
+/// of a 404.
+#[get("/content/{slug}")]
+pub async fn content_item(
+    req: HttpRequest,
+    slug: web::Path<String>,
+    tera: Data<TeraTemplates>,
+    sanity: Data<SanityClient>,
+) -> Result<HttpResponse, AppError> {
+    let (query, params) = GroqQuery::of_type("item")
+        .filter("active", Op::Eq, true)
+        .filter("slug.current", Op::Eq, slug.as_str())
+        .slice(0, 1)
+        .build()?;
+    // An invalid item is logged by `query_documents` and shown as missing
+    let (items, _): (Vec<Item>, _) = sanity
+        .query_documents(&query, &params)
+        .await
+        .map_err(|e| AppError::upstream("Sanity", e))?;
+    let Some(item) = items.into_iter().next() else {
+        if req.headers().get("HX-Trigger").is_some_and(|id| id == "content-item") {
+            return Ok(HttpResponse::Ok()
+                .insert_header(("HX-Location", r##"{"path":"/content","target":"#content-view"}"##))
+                .finish());
         }
+        return Err(AppError::NotFound);
     };
 
-    // Return as JSON Array of Strings
-    // HttpResponse::Ok().json(serde_json::json!(my_items[0..3]
-    //     .iter()
-    //     .map(|item| &item.name)
-    //     .collect::<Vec<&String>>()))
-
-    // Return as JSON Array of Objects
-    HttpResponse::Ok().json(serde_json::json!(my_items[0..my_items.len().min(3)]
-        .iter()
-        .map(|item| serde_json::json!({"name": &item.name}))
-        .collect::<Vec<serde_json::Value>>()))
+    let mut context = page_or_fragment_context(&req).await;
+    context.insert("navigation", &Navigation::new("content"));
+    context.insert("item", &item);
+    render_page_or_fragment(
+        &req,
+        &tera,
+        Template::ContentItem,
+        Template::ContentItemFragment,
+        &context,
+    )
+}
+
+/// Lists Sanity items as JSON, one page at a time.
+///
+/// Accepts the optional query parameters of `ItemListQuery`:
+/// - `size`: items per page, from 1 to 100 (default 20)
+/// - `sort` and `direction`: the field to order by, one of `SORT_FIELDS`, and `asc`
+///   or `desc` (default `_updatedAt` and `desc`); ties are ordered by `_id`
+///   and items without the sort field are left out
+/// - `active`: only items with this `active` flag
+/// - `fields`: comma-separated fields to return; `_id` and the sort field are always
+///   included
+/// - `cursor`: the `next_cursor` of the previous page
+///
+/// Responds with the `items`, a `page` object holding `size`, `count`, `sort`,
+/// `direction` and `next_cursor`, which is null on the last page, and `warnings`.
+/// Without `fields`, every item is checked to be a valid `Item`; invalid ones are
+/// left out and listed in `warnings` with their id and error. The `Link` header
+/// points to the `first` and `next` pages.
+#[get("/api/sanity")]
+pub async fn get_content(
+    req: HttpRequest,
+    sanity: Data<SanityClient>,
+) -> Result<HttpResponse, AppError> {
+    let params = web::Query::<ItemListQuery>::from_query(req.query_string())
+        .map_err(|e| AppError::BadRequest(e.to_string()))?
+        .into_inner();
+    let size = params.size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&size) {
+        return Err(AppError::BadRequest(format!("size must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+    let sort = params.sort.clone().unwrap_or_else(|| "_updatedAt".to_string());
+    if !SORT_FIELDS.contains(&sort.as_str()) {
+        return Err(AppError::BadRequest(format!(
+            "sort must be one of {}",
+            SORT_FIELDS.join(", ")
+        )));
+    }
+    let direction = params.direction.unwrap_or(Direction::Desc);
+
+    let mut query = GroqQuery::of_type("item").defined(&sort);
+    if let Some(active) = params.active {
+        query = query.filter("active", Op::Eq, active);
+    }
+    if let Some(cursor) = &params.cursor {
+        let cursor = PageCursor::decode(cursor)
+            .filter(|cursor| cursor.sort == sort && cursor.direction == direction)
+            .filter(|cursor| !cursor.value.is_null())
+            .ok_or_else(|| AppError::BadRequest("the cursor does not fit this sort".to_string()))?;
+        query = query.after(&sort, direction, cursor.value, &cursor.id);
+    }
+    // One extra item tells whether there is a next page
+    query = query.order(&sort, direction).order("_id", Direction::Asc).slice(0, size + 1);
+    if let Some(fields) = &params.fields {
+        let mut fields: Vec<&str> =
+            fields.split(',').map(str::trim).filter(|field| !field.is_empty()).collect();
+        for required in ["_id", sort.as_str()] {
+            if !fields.contains(&required) {
+                fields.push(required);
+            }
+        }
+        query = query.project(&fields);
+    }
+    let (query, query_params) = query.build()?;
+    let mut items: Vec<Value> =
+        sanity.query(&query, &query_params).await.map_err(|e| AppError::upstream("Sanity", e))?;
+
+    let next_cursor = if items.len() > size {
+        items.truncate(size);
+        items.last().map(|last| {
+            PageCursor {
+                sort: sort.clone(),
+                direction,
+                value: field_value(last, &sort),
+                id: last.get("_id").and_then(Value::as_str).unwrap_or_default().to_string(),
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    // Projections are partial documents, so only full ones are checked
+    let warnings = match params.fields {
+        Some(_) => Vec::new(),
+        None => {
+            let (valid, warnings) = decode_documents::<Item>(items);
+            items = valid.iter().filter_map(|item| serde_json::to_value(item).ok()).collect();
+            warnings
+        }
+    };
+
+    let mut links = vec![format!("<{}>; rel=\"first\"", page_url(&req, &params, None))];
+    if let Some(cursor) = &next_cursor {
+        links.push(format!("<{}>; rel=\"next\"", page_url(&req, &params, Some(cursor))));
+    }
+    Ok(HttpResponse::Ok().insert_header((LINK, links.join(", "))).json(serde_json::json!({
+        "items": items,
+        "page": {
+            "size": size,
+            "count": items.len(),
+            "sort": sort,
+            "direction": direction,
+            "next_cursor": next_cursor,
+        },
+        "warnings": warnings,
+    })))
+}
+
+/// Returns the value of the possibly nested `field` of a document.
+///
+/// Projections return nested fields under their full path, so that key is tried first.
+fn field_value(document: &Value, field: &str) -> Value {
+    document
+        .get(field)
+        .or_else(|| field.split('.').try_fold(document, |value, key| value.get(key)))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Returns the URL of the page of the current list starting at `cursor`.
+fn page_url(req: &HttpRequest, params: &ItemListQuery, cursor: Option<&str>) -> String {
+    let params = ItemListQuery { cursor: cursor.map(str::to_string), ..params.clone() };
+    match serde_urlencoded::to_string(&params) {
+        Ok(query) if !query.is_empty() => format!("{}?{}", req.path(), query),
+        _ => req.path().to_string(),
+    }
 }
 
 #[get("/api/open_dialog")]
@@ -1 +1,5 @@
+pub mod auth;
+pub mod error;
 pub mod handler;
+pub mod render;
+pub mod webhook;
@@ -0,0 +1,187 @@
+/// Template rendering for handlers.
+use crate::configs::config::SupabaseConfig;
+use crate::handlers::auth::resolve_user;
+use crate::handlers::error::AppError;
+use crate::models::model::{CurrentUser, Navigation, TeraTemplates};
+use crate::models::reload::TemplateReloader;
+use crate::models::sanity::SanityClient;
+use actix_web::http::header::VARY;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+use std::error::Error;
+use std::fmt;
+use tera::Context;
+
+/// Declares `Template` with a variant per template file, listing them all in one place.
+macro_rules! templates {
+    ($($variant:ident => $name:literal,)*) => {
+        /// A template rendered by the handlers.
+        ///
+        /// Handlers can only render these, so `Template::ALL` is the complete list the
+        /// `check-templates` command verifies.
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum Template {
+            $($variant,)*
+        }
+
+        impl Template {
+            /// Every template rendered by the handlers.
+            pub const ALL: &'static [Template] = &[$(Template::$variant,)*];
+
+            /// Returns the name of the template under `templates/`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Template::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+templates! {
+    Home => "home.html",
+    About => "about.html",
+    Login => "login.html",
+    Profile => "profile.html",
+    Content => "content.html",
+    ContentItem => "content-item.html",
+    ContentListFragment => "components/content-list.html",
+    ContentItemFragment => "components/content-item.html",
+    Counter => "comp.html",
+    CookieCounter => "comp-user.html",
+    DragAndDrop => "components/draganddrop.html",
+    Error => "error.html",
+    Unauthorized => "401.html",
+    Forbidden => "403.html",
+    NotFound => "404.html",
+    MethodNotAllowed => "405.html",
+}
+
+impl Template {
+    /// Returns the page shown for error responses with `status`, if it has its own.
+    pub fn for_status(status: StatusCode) -> Option<Template> {
+        match status {
+            StatusCode::UNAUTHORIZED => Some(Template::Unauthorized),
+            StatusCode::FORBIDDEN => Some(Template::Forbidden),
+            StatusCode::NOT_FOUND => Some(Template::NotFound),
+            StatusCode::METHOD_NOT_ALLOWED => Some(Template::MethodNotAllowed),
+            _ => None,
+        }
+    }
+}
+
+/// A template failed to render.
+#[derive(Debug)]
+pub struct RenderError {
+    pub template: Template,
+    pub source: tera::Error,
+}
+
+impl RenderError {
+    /// Returns the error and all of its causes, separated by colons.
+    pub fn cause(&self) -> String {
+        let mut cause = self.source.to_string();
+        let mut source = self.source.source();
+        while let Some(error) = source {
+            cause.push_str(": ");
+            cause.push_str(&error.to_string());
+            source = error.source();
+        }
+        cause
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to render {}: {}", self.template.name(), self.cause())
+    }
+}
+
+impl Error for RenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Renders `template` with `context`.
+pub fn render(
+    tera: &TeraTemplates,
+    template: Template,
+    context: &Context,
+) -> Result<String, RenderError> {
+    tera.render(template.name(), context).map_err(|source| RenderError { template, source })
+}
+
+/// Creates a Tera context describing the current user.
+///
+/// Inserts `is_authenticated` and `current_user` (id, email and role, or null for
+/// anonymous visitors) so every template can render the login state server-side.
+/// `auth_enabled` tells whether login is available at all, `cms_enabled` whether the
+/// Sanity content pages are, and `hot_reload` whether pages should reload when
+/// templates change.
+pub async fn user_context(req: &HttpRequest) -> Context {
+    let current_user = resolve_user(req).await.as_ref().map(CurrentUser::from);
+    let mut context = Context::new();
+    context.insert("auth_enabled", &req.app_data::<Data<SupabaseConfig>>().is_some());
+    context.insert("cms_enabled", &req.app_data::<Data<SanityClient>>().is_some());
+    context.insert("hot_reload", &req.app_data::<Data<TemplateReloader>>().is_some());
+    context.insert("is_authenticated", &current_user.is_some());
+    context.insert("current_user", &current_user);
+    context
+}
+
+/// Creates the context for `render_page_or_fragment`.
+///
+/// Fragments never show the login state, so partial htmx requests get an empty
+/// context and skip the session lookup of `user_context`.
+pub async fn page_or_fragment_context(req: &HttpRequest) -> Context {
+    if is_partial_request(req) {
+        Context::new()
+    } else {
+        user_context(req).await
+    }
+}
+
+/// Renders a specified template with navigation context.
+///
+/// Renders a template using Tera templating engine and includes navigation context based on the provided page.
+pub async fn render_template(
+    req: &HttpRequest,
+    tera: &Data<TeraTemplates>,
+    page: &str,
+    template: Template,
+) -> Result<HttpResponse, AppError> {
+    let navigation = Navigation::new(page);
+    let mut context = user_context(req).await;
+    context.insert(String::from("navigation"), &navigation);
+    Ok(HttpResponse::Ok().body(render(tera, template, &context)?))
+}
+
+/// Returns true for htmx requests that swap a fragment into the current page.
+///
+/// Boosted requests and history restores replace the whole body, so like normal
+/// navigation they need the full page.
+pub fn is_partial_request(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key("HX-Request")
+        && !headers.contains_key("HX-Boosted")
+        && !headers.contains_key("HX-History-Restore-Request")
+}
+
+/// Renders `fragment` for partial htmx requests and `page` otherwise.
+///
+/// `page` is expected to include `fragment`, so both show the same content. The
+/// response varies on `HX-Request` so caches keep the two apart.
+pub fn render_page_or_fragment(
+    req: &HttpRequest,
+    tera: &TeraTemplates,
+    page: Template,
+    fragment: Template,
+    context: &Context,
+) -> Result<HttpResponse, AppError> {
+    let template = if is_partial_request(req) { fragment } else { page };
+    Ok(HttpResponse::Ok()
+        .insert_header((VARY, "HX-Request"))
+        .body(render(tera, template, context)?))
+}
@@ -0,0 +1,220 @@
+/// Receiver of Sanity webhooks, keeping cached content and open pages up to date.
+use crate::models::events::{ContentChange, ContentEvents};
+use crate::models::sanity::SanityClient;
+use actix_web::web::{Bytes, Data};
+use actix_web::{post, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header carrying the timestamp and HMAC of a webhook request.
+const SIGNATURE_HEADER: &str = "sanity-webhook-signature";
+
+/// How far the signed timestamp may be from now, limiting replays of captured requests.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// The secret Sanity signs webhook requests with.
+pub struct WebhookSecret(pub String);
+
+/// Handles a Sanity webhook about a created, updated or deleted document.
+///
+/// Requests must be signed with the configured secret. The cached results of
+/// queries that may involve the document are dropped, and the change is published
+/// to `/api/sanity/events` so open pages can fetch the content again. The document
+/// type is read from the `_type` of the payload, so the webhook projection must
+/// include it.
+#[post("/webhooks/sanity")]
+pub async fn sanity_webhook(
+    req: HttpRequest,
+    body: Bytes,
+    secret: Data<WebhookSecret>,
+    sanity: Data<SanityClient>,
+    events: Data<ContentEvents>,
+) -> HttpResponse {
+    let signature = req.headers().get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok());
+    if let Err(reason) = verify_signature(&secret.0, signature, &body, SystemTime::now()) {
+        log::warn!("Rejected Sanity webhook: {}", reason);
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let header = |name: &str| {
+        req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+    };
+    let document: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let field = |name: &str| document.get(name).and_then(Value::as_str).map(str::to_string);
+    let change = ContentChange {
+        operation: header("sanity-operation").unwrap_or_else(|| "update".to_string()),
+        doc_type: field("_type"),
+        id: header("sanity-document-id").or_else(|| field("_id")),
+    };
+
+    let dropped = sanity.invalidate_document(change.doc_type.as_deref(), change.id.as_deref());
+    log::info!(
+        "Sanity {} of {} {}, dropped {} cached queries",
+        change.operation,
+        change.doc_type.as_deref().unwrap_or("unknown type"),
+        change.id.as_deref().unwrap_or("unknown id"),
+        dropped
+    );
+    events.publish(change);
+    HttpResponse::NoContent().finish()
+}
+
+/// Checks a `t=<timestamp>,v1=<signature>` header against the request body.
+///
+/// Sanity signs `<timestamp>.<body>` with HMAC-SHA256 and encodes the result as
+/// unpadded URL-safe base64. The timestamp is in milliseconds.
+fn verify_signature(
+    secret: &str,
+    header: Option<&str>,
+    body: &[u8],
+    now: SystemTime,
+) -> Result<(), &'static str> {
+    let header = header.ok_or("missing signature")?;
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = Some(value),
+            Some(("v1", value)) => signature = Some(value),
+            _ => {}
+        }
+    }
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+        return Err("malformed signature");
+    };
+
+    let signed_at = timestamp.parse::<u64>().map_err(|_| "malformed timestamp")?;
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    if now.abs_diff(signed_at) > MAX_CLOCK_SKEW.as_millis() as u64 {
+        return Err("timestamp too old or in the future");
+    }
+
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| "malformed signature")?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|_| "unusable secret")?;
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&signature).map_err(|_| "signature mismatch")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "webhook secret";
+    const BODY: &[u8] = br#"{"_id":"abc","_type":"item"}"#;
+
+    /// Signs `body` like Sanity does, returning the signature header.
+    fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(body);
+        format!("t={},v1={}", timestamp, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    fn millis(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let now = SystemTime::now();
+        let header = sign(SECRET, millis(now), BODY);
+        assert_eq!(verify_signature(SECRET, Some(&header), BODY, now), Ok(()));
+    }
+
+    #[test]
+    fn accepts_timestamps_within_the_clock_skew() {
+        let now = SystemTime::now();
+        for signed_at in [now - Duration::from_secs(60), now + Duration::from_secs(60)] {
+            let header = sign(SECRET, millis(signed_at), BODY);
+            assert_eq!(verify_signature(SECRET, Some(&header), BODY, now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn accepts_spaces_after_the_separator() {
+        let now = SystemTime::now();
+        let header = sign(SECRET, millis(now), BODY).replace(',', ", ");
+        assert_eq!(verify_signature(SECRET, Some(&header), BODY, now), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_tampered_body() {
+        let now = SystemTime::now();
+        let header = sign(SECRET, millis(now), BODY);
+        let tampered = br#"{"_id":"abd","_type":"item"}"#;
+        assert_eq!(
+            verify_signature(SECRET, Some(&header), tampered, now),
+            Err("signature mismatch")
+        );
+    }
+
+    #[test]
+    fn rejects_another_secret() {
+        let now = SystemTime::now();
+        let header = sign("other secret", millis(now), BODY);
+        assert_eq!(verify_signature(SECRET, Some(&header), BODY, now), Err("signature mismatch"));
+    }
+
+    #[test]
+    fn rejects_a_changed_timestamp() {
+        let now = SystemTime::now();
+        let header = sign(SECRET, millis(now), BODY).replacen(
+            &millis(now).to_string(),
+            &(millis(now) - 1).to_string(),
+            1,
+        );
+        assert_eq!(verify_signature(SECRET, Some(&header), BODY, now), Err("signature mismatch"));
+    }
+
+    #[test]
+    fn rejects_stale_and_future_timestamps() {
+        let now = SystemTime::now();
+        let skew = MAX_CLOCK_SKEW + Duration::from_secs(1);
+        for signed_at in [now - skew, now + skew] {
+            let header = sign(SECRET, millis(signed_at), BODY);
+            assert_eq!(
+                verify_signature(SECRET, Some(&header), BODY, now),
+                Err("timestamp too old or in the future")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_missing_header() {
+        assert_eq!(
+            verify_signature(SECRET, None, BODY, SystemTime::now()),
+            Err("missing signature")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let now = SystemTime::now();
+        let timestamp = millis(now);
+        let valid = sign(SECRET, timestamp, BODY);
+        let signature = valid.split_once("v1=").unwrap().1;
+        let cases = [
+            (String::new(), "malformed signature"),
+            ("garbage".to_string(), "malformed signature"),
+            (format!("t={}", timestamp), "malformed signature"),
+            (format!("v1={}", signature), "malformed signature"),
+            (format!("t=soon,v1={}", signature), "malformed timestamp"),
+            (format!("t={},v1=not base64!", timestamp), "malformed signature"),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(
+                verify_signature(SECRET, Some(&header), BODY, now),
+                Err(expected),
+                "header {:?}",
+                header
+            );
+        }
+    }
+}
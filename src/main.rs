@@ -3,73 +3,295 @@ mod configs;
 mod handlers;
 mod models;
 extern crate dotenv;
-extern crate sanity;
+use crate::configs::config::{AppConfig, LogFormat, SessionStoreKind};
+use crate::handlers::error::error_handlers;
 use crate::handlers::handler::{
-    about, close_dialog, content, cookie, draganddrop, events, get_comp, get_content,
-    get_leaderboard, hello, index, login, logout, open_dialog, ws_index,
+    about, close_dialog, cookie, draganddrop, events, get_comp, hello, index, no_route,
+    open_dialog, redirect_to_https, sanity_routes, sanity_unavailable_routes, supabase_routes,
+    supabase_unavailable_routes, template_reloads, ws_index,
 };
-use crate::models::model::{Counter, MySanityConfig, TeraTemplates};
-use actix_web::middleware::Logger;
-use actix_web::web::Data;
-use dotenv::dotenv;
+use crate::handlers::render::Template;
+use crate::handlers::webhook::{sanity_webhook, WebhookSecret};
+use crate::models::events::ContentEvents;
+use crate::models::image::ImageUrls;
+use crate::models::model::{Counter, TeraTemplates};
+use crate::models::portable_text::{CodeBlock, PortableTextRenderer};
+use crate::models::reload::TemplateReloader;
+use crate::models::sanity::SanityClient;
+use crate::models::session::{MemorySessionStore, PostgrestSessionStore, SessionStore};
+use crate::models::shutdown::ShutdownCoordinator;
+use crate::models::templates::{self, TemplateFilters, TEMPLATES_DIR};
+use crate::models::throttle::LoginThrottle;
+use actix_web::http::header::STRICT_TRANSPORT_SECURITY;
+use actix_web::middleware::{Condition, DefaultHeaders, Logger};
+use actix_web::web::{self, Data};
+use futures::future::{self, try_join_all};
 use futures::lock::Mutex;
 use postgrest::Postgrest;
-use tera::Tera;
+use std::io::Write;
+use std::sync::Arc;
 
 use actix_web::{App, HttpServer};
 
+/// Initializes the global logger with the given output format.
+fn init_logger(format: LogFormat) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                serde_json::json!({
+                    "timestamp": buf.timestamp().to_string(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                })
+            )
+        });
+    }
+    builder.init();
+}
+
+/// Waits for Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_requested() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen for SIGTERM");
+        future::select(Box::pin(tokio::signal::ctrl_c()), Box::pin(terminate.recv())).await;
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Parses the templates and reports missing ones, returning the process exit code.
+fn check_templates() -> i32 {
+    let tera = match templates::load(true) {
+        Ok(tera) => tera,
+        Err(e) => {
+            let mut error: Option<&dyn std::error::Error> = Some(&e);
+            while let Some(cause) = error {
+                eprintln!("{}", cause);
+                error = cause.source();
+            }
+            return 1;
+        }
+    };
+    let rendered: Vec<&str> = Template::ALL.iter().map(|template| template.name()).collect();
+    let problems = templates::missing_templates(&tera, &rendered);
+    if problems.is_empty() {
+        println!("All {} templates are valid", tera.templates.len());
+        return 0;
+    }
+    eprintln!("Found {} template problem(s):", problems.len());
+    for problem in &problems {
+        eprintln!("  - {}", problem);
+    }
+    1
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    dotenv().ok();
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("check-templates") => std::process::exit(check_templates()),
+        Some(command) => {
+            eprintln!("Unknown command `{}`, expected `check-templates`", command);
+            std::process::exit(2);
+        }
+    }
 
-    let tera_templates = Data::new(TeraTemplates {
-        tera: Tera::new("templates/**/*").expect("Problem setting up Tera"),
-    });
+    let config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    init_logger(config.log_format);
+    log::info!("Starting with the {:?} profile", config.profile);
+    if let Some(sanity) = &config.sanity {
+        log::info!("Using the Sanity dataset `{}`", sanity.dataset);
+    }
 
-    let counter = Data::new(Counter { count: Mutex::new(0) });
+    let image_urls = config.sanity.as_ref().map(ImageUrls::new);
+    let template_filters = TemplateFilters {
+        images: image_urls.clone(),
+        portable_text: PortableTextRenderer::new(image_urls).serializer("code", CodeBlock),
+    };
+    let tera_templates = Data::new(TeraTemplates::new(
+        templates::load(config.template_hot_reload).expect("Problem setting up Tera"),
+        template_filters,
+    ));
 
-    let supabase_url = std::env::var("SUPABASE_URL").expect("SUPABASE_URL not set");
+    // The watcher stops when dropped, so it is kept alive until the server exits
+    let (template_reloader, _template_watcher) = if config.template_hot_reload {
+        let reloader = Data::new(TemplateReloader::new(tera_templates.clone()));
+        match TemplateReloader::watch(reloader.clone(), TEMPLATES_DIR) {
+            Ok(watcher) => (Some(reloader), Some(watcher)),
+            Err(e) => {
+                log::warn!("Cannot watch templates, hot reload is disabled: {}", e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
 
-    let supabase = Data::new(Postgrest::new(supabase_url));
+    let counter = Data::new(Counter { count: Mutex::new(0) });
 
-    let (sanity_token_key, sanity_project_id) = (
-        std::env::var("SANITY_TOKEN_KEY").expect("SANITY_TOKEN_KEY not set"),
-        std::env::var("SANITY_PROJECT_ID").expect("SANITY_PROJECT_ID not set"),
-    );
+    let login_throttle = Data::new(LoginThrottle::new(
+        config.login_throttle.free_attempts,
+        config.login_throttle.base_lockout,
+        config.login_throttle.max_lockout,
+    ));
 
-    let sanity_config = Data::new(MySanityConfig {
-        sanity_config: Mutex::new(sanity::create(
-            &sanity_project_id,
-            "production",
-            &sanity_token_key,
-            true,
-        )),
+    let supabase = config.supabase.clone().map(|supabase_config| {
+        // Sessions hold Supabase tokens, so the Postgres store uses the service role key
+        let session_store: Arc<dyn SessionStore> = match config.session_store {
+            SessionStoreKind::Postgrest => {
+                let service_role_key = supabase_config.service_role_key.clone().unwrap_or_default();
+                Arc::new(PostgrestSessionStore::new(
+                    Postgrest::new(&supabase_config.url)
+                        .insert_header("apikey", &service_role_key)
+                        .insert_header("Authorization", format!("Bearer {}", service_role_key)),
+                ))
+            }
+            SessionStoreKind::Memory => Arc::new(MemorySessionStore::default()),
+        };
+        (
+            Data::new(Postgrest::new(&supabase_config.url)),
+            Data::from(session_store),
+            Data::new(supabase_config),
+        )
     });
+    if supabase.is_none() {
+        log::warn!("Supabase is not configured, login and leaderboard routes are disabled");
+    }
+
+    let sanity_client =
+        config.sanity.as_ref().map(|sanity_config| match SanityClient::new(sanity_config) {
+            Ok(client) => Data::new(client),
+            Err(e) => {
+                log::error!("Cannot create the Sanity client: {}", e);
+                std::process::exit(1);
+            }
+        });
+    if sanity_client.is_none() {
+        log::warn!("Sanity is not configured, CMS routes are disabled");
+    }
+    let content_events = Data::new(ContentEvents::new());
+    let webhook_secret = config
+        .sanity
+        .as_ref()
+        .and_then(|sanity_config| sanity_config.webhook_secret.clone())
+        .map(|secret| Data::new(WebhookSecret(secret)));
+    if sanity_client.is_some() && webhook_secret.is_none() {
+        log::info!("SANITY_WEBHOOK_SECRET is not set, /webhooks/sanity is disabled");
+    }
+
+    let host = config.server.host.clone();
+    let http_port = config.server.port;
+    let tls = match &config.server.tls {
+        Some(tls) => match tls.rustls_config() {
+            Ok(rustls_config) => Some((tls.https_port, tls.redirect_http, rustls_config)),
+            Err(e) => {
+                log::error!("Cannot load TLS certificate: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let hsts = config.server.tls.as_ref().and_then(|tls| tls.hsts_max_age);
+    let shutdown = Data::new(ShutdownCoordinator::new(config.server.shutdown_drain));
+    let app_shutdown = shutdown.clone();
+    let config = Data::new(config);
+    let redirect_config = config.clone();
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(sanity_config.clone())
-            .app_data(supabase.clone())
+            .app_data(config.clone())
             .app_data(counter.clone())
             .app_data(tera_templates.clone())
+            .app_data(app_shutdown.clone())
+            .configure(|cfg| match &supabase {
+                Some((postgrest, session_store, supabase_config)) => {
+                    cfg.app_data(postgrest.clone())
+                        .app_data(session_store.clone())
+                        .app_data(supabase_config.clone())
+                        .app_data(login_throttle.clone());
+                    supabase_routes(cfg);
+                }
+                None => supabase_unavailable_routes(cfg),
+            })
+            .configure(|cfg| match &sanity_client {
+                Some(sanity_client) => {
+                    cfg.app_data(sanity_client.clone()).app_data(content_events.clone());
+                    sanity_routes(cfg);
+                    if let Some(webhook_secret) = &webhook_secret {
+                        cfg.app_data(webhook_secret.clone()).service(sanity_webhook);
+                    }
+                }
+                None => sanity_unavailable_routes(cfg),
+            })
+            .configure(|cfg| {
+                if let Some(template_reloader) = &template_reloader {
+                    cfg.app_data(template_reloader.clone()).service(template_reloads);
+                }
+            })
             .service(open_dialog)
             .service(close_dialog)
             .service(draganddrop)
-            .service(get_content)
-            .service(login)
-            .service(logout)
             .service(about)
-            .service(content)
-            .service(get_leaderboard)
             .service(index)
             .service(hello)
             .service(events)
             .service(ws_index)
             .service(cookie)
             .service(get_comp)
+            .default_service(web::to(no_route))
+            .wrap(error_handlers())
+            .wrap(Condition::new(
+                hsts.is_some(),
+                DefaultHeaders::new().add((
+                    STRICT_TRANSPORT_SECURITY,
+                    format!("max-age={}", hsts.unwrap_or_default()),
+                )),
+            ))
             .wrap(Logger::default())
     })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    .disable_signals();
+
+    // Signals are handled here so open connections are drained before the servers stop
+    let mut servers = Vec::new();
+    match tls {
+        None => servers.push(server.bind((host, http_port))?.run()),
+        Some((https_port, redirect_http, rustls_config)) => {
+            servers.push(server.bind_rustls_021((host.clone(), https_port), rustls_config)?.run());
+            if redirect_http {
+                let redirect = HttpServer::new(move || {
+                    App::new()
+                        .app_data(redirect_config.clone())
+                        .default_service(web::to(redirect_to_https))
+                        .wrap(Logger::default())
+                })
+                .disable_signals()
+                .bind((host, http_port))?
+                .run();
+                servers.push(redirect);
+            }
+        }
+    }
+
+    let handles: Vec<_> = servers.iter().map(|server| server.handle()).collect();
+    actix_web::rt::spawn(async move {
+        shutdown_requested().await;
+        shutdown.shutdown().await;
+        for handle in handles {
+            handle.stop(true).await;
+        }
+    });
+    try_join_all(servers).await.map(|_| ())
 }
@@ -0,0 +1,276 @@
+/// An in-memory cache for query results with stale-while-revalidate.
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A fetch of one key, shared by every caller waiting for it.
+type InFlight<V, E> = Shared<BoxFuture<'static, Result<V, E>>>;
+
+/// A registered fetch and the id telling it apart from later fetches of the same key.
+struct Fetch<V, E> {
+    id: u64,
+    in_flight: InFlight<V, E>,
+}
+
+/// A cached value and when it was fetched.
+struct Entry<V> {
+    value: V,
+    fetched_at: Instant,
+}
+
+/// The mutable state of a `QueryCache`.
+struct State<V, E> {
+    entries: HashMap<String, Entry<V>>,
+    in_flight: HashMap<String, Fetch<V, E>>,
+    next_fetch_id: u64,
+}
+
+/// Caches query results by key.
+///
+/// A value is fresh for `ttl`. For `stale_while_revalidate` after that it is still
+/// returned, while a background fetch refreshes it. Older values are fetched again
+/// before returning. Concurrent fetches of the same key are coalesced into one, and
+/// failed fetches are not cached, so a failed refresh keeps the stale value. When
+/// more than `max_entries` keys are cached, the oldest entry is evicted. A zero
+/// `ttl` disables caching.
+pub struct QueryCache<V, E> {
+    state: Arc<Mutex<State<V, E>>>,
+    ttl: Duration,
+    stale_while_revalidate: Duration,
+    max_entries: usize,
+}
+
+impl<V, E> QueryCache<V, E>
+where
+    V: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    /// Creates a new, empty QueryCache.
+    pub fn new(ttl: Duration, stale_while_revalidate: Duration, max_entries: usize) -> Self {
+        QueryCache {
+            state: Arc::new(Mutex::new(State {
+                entries: HashMap::new(),
+                in_flight: HashMap::new(),
+                next_fetch_id: 0,
+            })),
+            ttl,
+            stale_while_revalidate,
+            max_entries,
+        }
+    }
+
+    /// Returns the cached value of `key`, calling `fetch` when it is missing or stale.
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>> + Send + 'static,
+    {
+        if self.ttl.is_zero() {
+            return fetch().await;
+        }
+
+        let in_flight = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = state.entries.get(key) {
+                let age = entry.fetched_at.elapsed();
+                if age < self.ttl {
+                    return Ok(entry.value.clone());
+                }
+                if age < self.ttl + self.stale_while_revalidate {
+                    let value = entry.value.clone();
+                    if !state.in_flight.contains_key(key) {
+                        let refresh = self.start_fetch(&mut state, key, fetch());
+                        actix_web::rt::spawn(refresh);
+                    }
+                    return Ok(value);
+                }
+            }
+            match state.in_flight.get(key) {
+                Some(fetch) => fetch.in_flight.clone(),
+                None => self.start_fetch(&mut state, key, fetch()),
+            }
+        };
+        in_flight.await
+    }
+
+    /// Removes the cached values of all keys matching `predicate` and returns how many.
+    ///
+    /// Fetches of these keys that are still in flight complete for their callers,
+    /// but their results are not cached.
+    pub fn invalidate(&self, predicate: impl Fn(&str) -> bool) -> usize {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let before = state.entries.len();
+        state.entries.retain(|key, _| !predicate(key));
+        state.in_flight.retain(|key, _| !predicate(key));
+        before - state.entries.len()
+    }
+
+    /// Registers `fetch` as the in-flight fetch of `key` and returns it.
+    ///
+    /// The fetch stores its result and unregisters itself when it completes, unless
+    /// it was invalidated in the meantime.
+    fn start_fetch<Fut>(&self, state: &mut State<V, E>, key: &str, fetch: Fut) -> InFlight<V, E>
+    where
+        Fut: Future<Output = Result<V, E>> + Send + 'static,
+    {
+        let shared_state = self.state.clone();
+        let owned_key = key.to_string();
+        let max_entries = self.max_entries;
+        let id = state.next_fetch_id;
+        state.next_fetch_id += 1;
+        let in_flight = async move {
+            let result = fetch.await;
+            let mut state = shared_state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.in_flight.get(&owned_key).map(|fetch| fetch.id) != Some(id) {
+                return result;
+            }
+            state.in_flight.remove(&owned_key);
+            if let Ok(value) = &result {
+                state
+                    .entries
+                    .insert(owned_key, Entry { value: value.clone(), fetched_at: Instant::now() });
+                if state.entries.len() > max_entries {
+                    let oldest = state
+                        .entries
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.fetched_at)
+                        .map(|(key, _)| key.clone());
+                    if let Some(oldest) = oldest {
+                        state.entries.remove(&oldest);
+                    }
+                }
+            }
+            result
+        }
+        .boxed()
+        .shared();
+        state.in_flight.insert(key.to_string(), Fetch { id, in_flight: in_flight.clone() });
+        in_flight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::rt::time::sleep;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    type Cache = QueryCache<u32, String>;
+
+    /// Returns a fetch counting its calls in `calls` that resolves to `result` after `delay`.
+    fn fetch(
+        calls: &Arc<AtomicUsize>,
+        result: Result<u32, String>,
+        delay: Duration,
+    ) -> impl FnOnce() -> BoxFuture<'static, Result<u32, String>> {
+        let calls = calls.clone();
+        move || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                sleep(delay).await;
+                result
+            }
+            .boxed()
+        }
+    }
+
+    #[actix_web::test]
+    async fn returns_fresh_values_without_fetching() {
+        let cache = Cache::new(Duration::from_secs(60), Duration::ZERO, 10);
+        let calls = Arc::new(AtomicUsize::new(0));
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(1), Duration::ZERO)).await, Ok(1));
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(2), Duration::ZERO)).await, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn returns_stale_values_while_one_refresh_runs() {
+        let cache = Cache::new(Duration::from_millis(200), Duration::from_secs(60), 10);
+        let calls = Arc::new(AtomicUsize::new(0));
+        cache.get_or_fetch("a", fetch(&calls, Ok(1), Duration::ZERO)).await.unwrap();
+        sleep(Duration::from_millis(250)).await;
+
+        let refresh = Duration::from_millis(20);
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(2), refresh)).await, Ok(1));
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(3), refresh)).await, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(4), Duration::ZERO)).await, Ok(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn shares_one_fetch_between_concurrent_misses() {
+        let cache = Cache::new(Duration::from_secs(60), Duration::ZERO, 10);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let delay = Duration::from_millis(20);
+        let results = futures::future::join_all(
+            (0..5).map(|n| cache.get_or_fetch("a", fetch(&calls, Ok(n), delay))),
+        )
+        .await;
+        assert_eq!(results, vec![Ok(0); 5]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn keeps_stale_values_when_a_refresh_fails() {
+        let cache = Cache::new(Duration::from_millis(20), Duration::from_secs(60), 10);
+        let calls = Arc::new(AtomicUsize::new(0));
+        cache.get_or_fetch("a", fetch(&calls, Ok(1), Duration::ZERO)).await.unwrap();
+        sleep(Duration::from_millis(40)).await;
+
+        let failure = Err("down".to_string());
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, failure, Duration::ZERO)).await, Ok(1));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            cache.get_or_fetch("a", fetch(&calls, Ok(3), Duration::from_secs(1))).await,
+            Ok(1)
+        );
+    }
+
+    #[actix_web::test]
+    async fn does_not_cache_fetches_invalidated_in_flight() {
+        let cache = Cache::new(Duration::from_secs(60), Duration::ZERO, 10);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let get = cache.get_or_fetch("a", fetch(&calls, Ok(1), Duration::from_millis(50)));
+        let invalidate = async {
+            sleep(Duration::from_millis(10)).await;
+            cache.invalidate(|key| key == "a")
+        };
+        let (value, _) = futures::join!(get, invalidate);
+        assert_eq!(value, Ok(1));
+
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(2), Duration::ZERO)).await, Ok(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn evicts_the_oldest_entry_past_max_entries() {
+        let cache = Cache::new(Duration::from_secs(60), Duration::ZERO, 2);
+        let calls = Arc::new(AtomicUsize::new(0));
+        for (n, key) in ["a", "b", "c"].into_iter().enumerate() {
+            cache.get_or_fetch(key, fetch(&calls, Ok(n as u32), Duration::ZERO)).await.unwrap();
+            sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(cache.get_or_fetch("c", fetch(&calls, Ok(9), Duration::ZERO)).await, Ok(2));
+        assert_eq!(cache.get_or_fetch("b", fetch(&calls, Ok(9), Duration::ZERO)).await, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.get_or_fetch("a", fetch(&calls, Ok(9), Duration::ZERO)).await, Ok(9));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[actix_web::test]
+    async fn counts_invalidated_entries() {
+        let cache = Cache::new(Duration::from_secs(60), Duration::ZERO, 10);
+        let calls = Arc::new(AtomicUsize::new(0));
+        for key in ["item:a", "item:b", "page:a"] {
+            cache.get_or_fetch(key, fetch(&calls, Ok(1), Duration::ZERO)).await.unwrap();
+        }
+        assert_eq!(cache.invalidate(|key| key.starts_with("item:")), 2);
+        assert_eq!(cache.invalidate(|key| key.starts_with("item:")), 0);
+    }
+}
@@ -0,0 +1,45 @@
+/// Broadcasting of CMS content changes to open pages.
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// How many changes a slow subscriber may fall behind before it skips some.
+const CAPACITY: usize = 64;
+
+/// A document created, updated or deleted in the CMS.
+#[derive(Clone, Debug, Serialize)]
+pub struct ContentChange {
+    /// `create`, `update` or `delete`, as reported by Sanity.
+    pub operation: String,
+    #[serde(rename = "type")]
+    pub doc_type: Option<String>,
+    pub id: Option<String>,
+}
+
+/// Publishes content changes to the SSE streams of open pages.
+pub struct ContentEvents {
+    sender: broadcast::Sender<ContentChange>,
+}
+
+impl ContentEvents {
+    /// Creates a new ContentEvents without subscribers.
+    pub fn new() -> Self {
+        ContentEvents { sender: broadcast::channel(CAPACITY).0 }
+    }
+
+    /// Sends `change` to all current subscribers.
+    pub fn publish(&self, change: ContentChange) {
+        // Sending only fails when no page is listening
+        let _ = self.sender.send(change);
+    }
+
+    /// Returns a receiver of all changes published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ContentChange> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for ContentEvents {
+    fn default() -> Self {
+        ContentEvents::new()
+    }
+}
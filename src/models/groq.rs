@@ -0,0 +1,327 @@
+/// A builder for GROQ queries with bound parameters.
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// A comparison operator of a filter predicate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Eq,
+    Lt,
+    Gt,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Lt => "<",
+            Op::Gt => ">",
+        }
+    }
+}
+
+/// Sort direction of an ordering.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Asc => "asc",
+            Direction::Desc => "desc",
+        }
+    }
+}
+
+/// An error in a query built from user input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroqError {
+    /// A field path is not a dotted list of identifiers.
+    InvalidField(String),
+}
+
+impl fmt::Display for GroqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroqError::InvalidField(field) => write!(f, "invalid field `{}`", field),
+        }
+    }
+}
+
+impl std::error::Error for GroqError {}
+
+/// A GROQ query over all documents, narrowed by filters and shaped by ordering,
+/// slicing and a projection.
+///
+/// Field paths are validated and every value is bound as a `$param` instead of
+/// being spliced into the query, so queries can be built safely from user input.
+/// Invalid input is reported by `build`.
+///
+/// ```ignore
+/// let (query, params) = GroqQuery::of_type("item")
+///     .filter("active", Op::Eq, true)
+///     .order("_createdAt", Direction::Desc)
+///     .slice(0, 10)
+///     .project(&["name", "slug.current"])
+///     .build()?;
+/// // *[_type == $p0 && active == $p1] | order(_createdAt desc) [0...10] {name, "slug.current": slug.current}
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GroqQuery {
+    filters: Vec<String>,
+    ordering: Vec<String>,
+    slice: Option<(usize, usize)>,
+    projection: Option<Vec<String>>,
+    params: Map<String, Value>,
+    error: Option<GroqError>,
+}
+
+impl GroqQuery {
+    /// Creates a query over all documents.
+    pub fn new() -> Self {
+        GroqQuery::default()
+    }
+
+    /// Creates a query over the documents of `type_name`.
+    pub fn of_type(type_name: &str) -> Self {
+        GroqQuery::new().filter("_type", Op::Eq, type_name)
+    }
+
+    /// Adds a predicate comparing `field` to `value`. All predicates must match.
+    pub fn filter(mut self, field: &str, op: Op, value: impl Into<Value>) -> Self {
+        if let Some(field) = self.field(field) {
+            let param = self.bind(value.into());
+            self.filters.push(format!("{} {} ${}", field, op.as_str(), param));
+        }
+        self
+    }
+
+    /// Keeps the documents where `field` is set and not null.
+    pub fn defined(mut self, field: &str) -> Self {
+        if let Some(field) = self.field(field) {
+            self.filters.push(format!("defined({})", field));
+        }
+        self
+    }
+
+    /// Keeps the documents following the one with `value` in `field` and `id` in `_id`,
+    /// for keyset pagination.
+    ///
+    /// The query must be ordered by `field` in `direction` and then by `_id` ascending.
+    pub fn after(mut self, field: &str, direction: Direction, value: Value, id: &str) -> Self {
+        if let Some(field) = self.field(field) {
+            let value = self.bind(value);
+            let id = self.bind(Value::from(id));
+            let op = match direction {
+                Direction::Asc => Op::Gt,
+                Direction::Desc => Op::Lt,
+            };
+            self.filters.push(format!(
+                "({} {} ${} || ({} == ${} && _id > ${}))",
+                field,
+                op.as_str(),
+                value,
+                field,
+                value,
+                id
+            ));
+        }
+        self
+    }
+
+    /// Orders by `field`. Later orderings break ties of earlier ones.
+    pub fn order(mut self, field: &str, direction: Direction) -> Self {
+        if let Some(field) = self.field(field) {
+            self.ordering.push(format!("{} {}", field, direction.as_str()));
+        }
+        self
+    }
+
+    /// Keeps the results from index `start` up to, but excluding, `end`.
+    pub fn slice(mut self, start: usize, end: usize) -> Self {
+        self.slice = Some((start, end.max(start)));
+        self
+    }
+
+    /// Returns only `fields` of each document.
+    ///
+    /// Nested paths such as `slug.current` are returned under their full path as key.
+    pub fn project(mut self, fields: &[&str]) -> Self {
+        let mut projection = Vec::new();
+        for field in fields {
+            let Some(field) = self.field(field) else { return self };
+            projection.push(if field.contains('.') {
+                format!("\"{}\": {}", field, field)
+            } else {
+                field
+            });
+        }
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Returns the query string and its parameter bindings.
+    pub fn build(self) -> Result<(String, Map<String, Value>), GroqError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let mut query = String::from("*");
+        if !self.filters.is_empty() {
+            query.push_str(&format!("[{}]", self.filters.join(" && ")));
+        }
+        if !self.ordering.is_empty() {
+            query.push_str(&format!(" | order({})", self.ordering.join(", ")));
+        }
+        if let Some((start, end)) = self.slice {
+            query.push_str(&format!(" [{}...{}]", start, end));
+        }
+        if let Some(projection) = self.projection {
+            query.push_str(&format!(" {{{}}}", projection.join(", ")));
+        }
+        Ok((query, self.params))
+    }
+
+    /// Validates a field path, recording an error if it is invalid.
+    fn field(&mut self, field: &str) -> Option<String> {
+        if field.split('.').all(is_identifier) {
+            Some(field.to_string())
+        } else {
+            self.error.get_or_insert(GroqError::InvalidField(field.to_string()));
+            None
+        }
+    }
+
+    /// Binds `value` to a generated parameter name and returns the name.
+    fn bind(&mut self, value: Value) -> String {
+        let name = format!("p{}", self.params.len());
+        self.params.insert(name.clone(), value);
+        name
+    }
+}
+
+/// Returns true if `name` is a GROQ identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn builds_filters_ordering_slice_and_projection() {
+        let (query, params) = GroqQuery::of_type("item")
+            .filter("active", Op::Eq, true)
+            .order("_createdAt", Direction::Desc)
+            .order("_id", Direction::Asc)
+            .slice(0, 10)
+            .project(&["name", "slug.current"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            query,
+            "*[_type == $p0 && active == $p1] | order(_createdAt desc, _id asc) [0...10] \
+             {name, \"slug.current\": slug.current}"
+        );
+        assert_eq!(params.get("p0"), Some(&json!("item")));
+        assert_eq!(params.get("p1"), Some(&json!(true)));
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    fn builds_an_unfiltered_query() {
+        let (query, params) = GroqQuery::new().build().unwrap();
+        assert_eq!(query, "*");
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn clamps_an_inverted_slice() {
+        let (query, _) = GroqQuery::new().slice(5, 2).build().unwrap();
+        assert_eq!(query, "* [5...5]");
+    }
+
+    #[test]
+    fn builds_keyset_predicates() {
+        let (query, params) = GroqQuery::new()
+            .after("_updatedAt", Direction::Desc, json!("2024-01-01"), "abc")
+            .after("name", Direction::Asc, json!("m"), "xyz")
+            .build()
+            .unwrap();
+        assert_eq!(
+            query,
+            "*[(_updatedAt < $p0 || (_updatedAt == $p0 && _id > $p1)) && \
+             (name > $p2 || (name == $p2 && _id > $p3))]"
+        );
+        assert_eq!(params.get("p1"), Some(&json!("abc")));
+        assert_eq!(params.get("p3"), Some(&json!("xyz")));
+    }
+
+    #[test]
+    fn builds_keyset_predicates_over_defined_fields() {
+        // A null cursor value would compare as null and end the pages early, so
+        // documents without the sort field are left out of the whole listing
+        let (query, params) = GroqQuery::of_type("item")
+            .defined("name")
+            .after("name", Direction::Asc, Value::Null, "a")
+            .build()
+            .unwrap();
+        assert_eq!(
+            query,
+            "*[_type == $p0 && defined(name) && (name > $p1 || (name == $p1 && _id > $p2))]"
+        );
+        assert_eq!(params.get("p1"), Some(&Value::Null));
+        assert!(GroqQuery::new().defined("name)").build().is_err());
+    }
+
+    #[test]
+    fn binds_values_instead_of_splicing_them() {
+        let injection = "\"] | *[_type == \"secret";
+        let (query, params) = GroqQuery::new().filter("name", Op::Eq, injection).build().unwrap();
+        assert_eq!(query, "*[name == $p0]");
+        assert_eq!(params.get("p0"), Some(&json!(injection)));
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        for field in ["", "1name", "name]", "a..b", "a.", "name || true", "slug[0]", "$p0"] {
+            assert_eq!(
+                GroqQuery::new().filter(field, Op::Eq, 1).build(),
+                Err(GroqError::InvalidField(field.to_string())),
+                "filter on {:?}",
+                field
+            );
+            assert!(GroqQuery::new().order(field, Direction::Asc).build().is_err());
+            assert!(GroqQuery::new().project(&["name", field]).build().is_err());
+            assert!(GroqQuery::new().after(field, Direction::Asc, json!(1), "id").build().is_err());
+        }
+    }
+
+    #[test]
+    fn reports_the_first_invalid_field() {
+        let result = GroqQuery::new()
+            .filter("ok", Op::Eq, 1)
+            .order("bad field", Direction::Asc)
+            .project(&["worse)"])
+            .build();
+        assert_eq!(result, Err(GroqError::InvalidField("bad field".to_string())));
+    }
+
+    #[test]
+    fn recognizes_identifiers() {
+        for name in ["a", "_id", "_createdAt", "name2", "A_b_C"] {
+            assert!(is_identifier(name), "{:?}", name);
+        }
+        for name in ["", "2a", "a-b", "a.b", "a b", "é", "$a", "a\"", "a\n"] {
+            assert!(!is_identifier(name), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+/// URLs of images served by the Sanity image CDN.
+use crate::configs::config::SanityConfig;
+use crate::models::model::{ImageCrop, ImageHotspot};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// Base URL of the Sanity image CDN.
+const CDN_URL: &str = "https://cdn.sanity.io/images";
+
+/// An error in an image reference or image option.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageError {
+    /// The asset reference is not of the form `image-<id>-<width>x<height>-<format>`.
+    InvalidRef(String),
+    /// An option such as `fit` or `format` has an unknown value.
+    InvalidOption(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::InvalidRef(reference) => {
+                write!(f, "invalid image reference `{}`", reference)
+            }
+            ImageError::InvalidOption(option) => write!(f, "invalid image option `{}`", option),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// An image asset, parsed from a reference like `image-Tb9Ew8CXIwaY6R1kjMvI0uRR-2000x3000-jpg`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageRef {
+    pub id: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+}
+
+impl FromStr for ImageRef {
+    type Err = ImageError;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let invalid = || ImageError::InvalidRef(reference.to_string());
+        let rest = reference.strip_prefix("image-").ok_or_else(invalid)?;
+        let (rest, format) = rest.rsplit_once('-').ok_or_else(invalid)?;
+        let (id, dimensions) = rest.rsplit_once('-').ok_or_else(invalid)?;
+        let (width, height) = dimensions.split_once('x').ok_or_else(invalid)?;
+        let width: u32 = width.parse().map_err(|_| invalid())?;
+        let height: u32 = height.parse().map_err(|_| invalid())?;
+        if id.is_empty() || format.is_empty() || width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(ImageRef { id: id.to_string(), width, height, format: format.to_string() })
+    }
+}
+
+/// How the image is fitted into the requested width and height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fit {
+    Clip,
+    Crop,
+    Fill,
+    FillMax,
+    Max,
+    Scale,
+    Min,
+}
+
+impl FromStr for Fit {
+    type Err = ImageError;
+
+    fn from_str(fit: &str) -> Result<Self, Self::Err> {
+        match fit {
+            "clip" => Ok(Fit::Clip),
+            "crop" => Ok(Fit::Crop),
+            "fill" => Ok(Fit::Fill),
+            "fillmax" => Ok(Fit::FillMax),
+            "max" => Ok(Fit::Max),
+            "scale" => Ok(Fit::Scale),
+            "min" => Ok(Fit::Min),
+            _ => Err(ImageError::InvalidOption(format!("fit={}", fit))),
+        }
+    }
+}
+
+impl Fit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Fit::Clip => "clip",
+            Fit::Crop => "crop",
+            Fit::Fill => "fill",
+            Fit::FillMax => "fillmax",
+            Fit::Max => "max",
+            Fit::Scale => "scale",
+            Fit::Min => "min",
+        }
+    }
+}
+
+/// The file format the CDN converts the image to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Jpg,
+    /// Progressive JPEG.
+    Pjpg,
+    Png,
+    Webp,
+}
+
+impl FromStr for ImageFormat {
+    type Err = ImageError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "jpg" => Ok(ImageFormat::Jpg),
+            "pjpg" => Ok(ImageFormat::Pjpg),
+            "png" => Ok(ImageFormat::Png),
+            "webp" => Ok(ImageFormat::Webp),
+            _ => Err(ImageError::InvalidOption(format!("format={}", format))),
+        }
+    }
+}
+
+impl ImageFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            ImageFormat::Jpg => "jpg",
+            ImageFormat::Pjpg => "pjpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
+/// Creates image URL builders for the assets of one Sanity project and dataset.
+#[derive(Clone, Debug)]
+pub struct ImageUrls {
+    base_url: String,
+}
+
+impl ImageUrls {
+    /// Creates a new ImageUrls for the project and dataset in `config`.
+    pub fn new(config: &SanityConfig) -> Self {
+        ImageUrls { base_url: format!("{}/{}/{}", CDN_URL, config.project_id, config.dataset) }
+    }
+
+    /// Starts a URL of the asset with the reference `asset_ref`.
+    pub fn asset(&self, asset_ref: &str) -> Result<ImageUrlBuilder, ImageError> {
+        Ok(ImageUrlBuilder {
+            base_url: self.base_url.clone(),
+            asset: asset_ref.parse()?,
+            crop: None,
+            hotspot: None,
+            width: None,
+            height: None,
+            fit: None,
+            format: None,
+            quality: None,
+        })
+    }
+
+    /// Starts a URL of an image given as JSON.
+    ///
+    /// Accepts an image object with an `asset` and optional `crop` and `hotspot`,
+    /// an asset object with a `_ref`, or the reference itself.
+    pub fn image(&self, image: &Value) -> Result<ImageUrlBuilder, ImageError> {
+        if let Some(asset_ref) = image.as_str() {
+            return self.asset(asset_ref);
+        }
+        let asset = image.get("asset").unwrap_or(image);
+        let asset_ref = asset
+            .get("_ref")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ImageError::InvalidRef(image.to_string()))?;
+        let field = |name: &str| image.get(name).cloned().unwrap_or_default();
+        Ok(self
+            .asset(asset_ref)?
+            .crop(serde_json::from_value(field("crop")).unwrap_or_default())
+            .hotspot(serde_json::from_value(field("hotspot")).unwrap_or_default()))
+    }
+}
+
+/// A URL of an image on the Sanity CDN, resized and converted by the CDN.
+///
+/// ```ignore
+/// let url = image_urls.asset(&item.image.asset._ref)?
+///     .crop(item.image.crop)
+///     .hotspot(item.image.hotspot)
+///     .width(400)
+///     .height(300)
+///     .format(ImageFormat::Webp)
+///     .url();
+/// ```
+///
+/// When the image has a crop or hotspot, only the cropped part is used. If both a
+/// width and a height are requested, that part is further cut to their aspect
+/// ratio, keeping the hotspot as centered as possible.
+#[derive(Clone, Debug)]
+pub struct ImageUrlBuilder {
+    base_url: String,
+    asset: ImageRef,
+    crop: Option<ImageCrop>,
+    hotspot: Option<ImageHotspot>,
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<ImageFormat>,
+    quality: Option<u8>,
+}
+
+impl ImageUrlBuilder {
+    /// Sets the crop chosen in the Studio.
+    pub fn crop(mut self, crop: Option<ImageCrop>) -> Self {
+        self.crop = crop;
+        self
+    }
+
+    /// Sets the hotspot chosen in the Studio.
+    pub fn hotspot(mut self, hotspot: Option<ImageHotspot>) -> Self {
+        self.hotspot = hotspot;
+        self
+    }
+
+    /// Sets the width in pixels.
+    pub fn width(mut self, width: u32) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// Sets the height in pixels.
+    pub fn height(mut self, height: u32) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    /// Sets how the image is fitted into the width and height.
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = Some(fit);
+        self
+    }
+
+    /// Sets the format the image is converted to.
+    pub fn format(mut self, format: ImageFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the compression quality from 1 to 100.
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality.clamp(1, 100));
+        self
+    }
+
+    /// Returns the URL.
+    pub fn url(&self) -> String {
+        let asset = &self.asset;
+        let mut url = format!(
+            "{}/{}-{}x{}.{}",
+            self.base_url, asset.id, asset.width, asset.height, asset.format
+        );
+        let mut params = Vec::new();
+        if let Some((left, top, width, height)) = self.rect() {
+            params.push(format!("rect={},{},{},{}", left, top, width, height));
+        }
+        if let Some(width) = self.width {
+            params.push(format!("w={}", width));
+        }
+        if let Some(height) = self.height {
+            params.push(format!("h={}", height));
+        }
+        if let Some(fit) = self.fit {
+            params.push(format!("fit={}", fit.as_str()));
+        }
+        if let Some(format) = self.format {
+            params.push(format!("fm={}", format.as_str()));
+        }
+        if let Some(quality) = self.quality {
+            params.push(format!("q={}", quality));
+        }
+        if !params.is_empty() {
+            url.push('?');
+            url.push_str(&params.join("&"));
+        }
+        url
+    }
+
+    /// Returns a `srcset` attribute value with a URL for each of `widths`.
+    ///
+    /// A requested height is scaled along with the width to keep the aspect ratio.
+    pub fn srcset(&self, widths: &[u32]) -> String {
+        let base_width = self.width.unwrap_or(self.asset.width);
+        widths
+            .iter()
+            .map(|&width| {
+                let mut builder = self.clone().width(width);
+                if let Some(height) = self.height {
+                    let scaled = f64::from(height) * f64::from(width) / f64::from(base_width);
+                    builder = builder.height(scaled.round().max(1.0) as u32);
+                }
+                format!("{} {}w", builder.url(), width)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns the part of the source image to use as `(left, top, width, height)` in
+    /// pixels, or `None` for the whole image.
+    fn rect(&self) -> Option<(u32, u32, u32, u32)> {
+        if self.crop.is_none() && self.hotspot.is_none() {
+            return None;
+        }
+        let image_width = f64::from(self.asset.width);
+        let image_height = f64::from(self.asset.height);
+        let crop = self.crop.unwrap_or(ImageCrop { top: 0.0, bottom: 0.0, left: 0.0, right: 0.0 });
+        let hotspot =
+            self.hotspot.unwrap_or(ImageHotspot { x: 0.5, y: 0.5, height: 1.0, width: 1.0 });
+
+        let crop_left = crop.left * image_width;
+        let crop_top = crop.top * image_height;
+        let crop_width = image_width - crop.right * image_width - crop_left;
+        let crop_height = image_height - crop.bottom * image_height - crop_top;
+        let (mut left, mut top, mut width, mut height) =
+            (crop_left, crop_top, crop_width, crop_height);
+
+        if let (Some(requested_width), Some(requested_height)) = (self.width, self.height) {
+            if matches!(self.fit, None | Some(Fit::Crop)) {
+                let aspect_ratio = f64::from(requested_width) / f64::from(requested_height);
+                if crop_width / crop_height > aspect_ratio {
+                    width = crop_height * aspect_ratio;
+                    left = (hotspot.x * image_width - width / 2.0)
+                        .min(crop_left + crop_width - width)
+                        .max(crop_left);
+                } else {
+                    height = crop_width / aspect_ratio;
+                    top = (hotspot.y * image_height - height / 2.0)
+                        .min(crop_top + crop_height - height)
+                        .max(crop_top);
+                }
+            }
+        }
+
+        let rect =
+            (left.round() as u32, top.round() as u32, width.round() as u32, height.round() as u32);
+        (rect != (0, 0, self.asset.width, self.asset.height)).then_some(rect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const REF: &str = "image-Tb9Ew8CXIwaY6R1kjMvI0uRR-2000x3000-jpg";
+
+    fn urls() -> ImageUrls {
+        ImageUrls { base_url: format!("{}/project/production", CDN_URL) }
+    }
+
+    /// Returns a builder for a `width` by `height` image.
+    fn image(width: u32, height: u32) -> ImageUrlBuilder {
+        urls().asset(&format!("image-abc-{}x{}-png", width, height)).unwrap()
+    }
+
+    fn hotspot(x: f64, y: f64) -> Option<ImageHotspot> {
+        Some(ImageHotspot { x, y, height: 0.1, width: 0.1 })
+    }
+
+    fn crop(top: f64, bottom: f64, left: f64, right: f64) -> Option<ImageCrop> {
+        Some(ImageCrop { top, bottom, left, right })
+    }
+
+    #[test]
+    fn parses_image_refs() {
+        let image: ImageRef = REF.parse().unwrap();
+        assert_eq!(
+            image,
+            ImageRef {
+                id: "Tb9Ew8CXIwaY6R1kjMvI0uRR".to_string(),
+                width: 2000,
+                height: 3000,
+                format: "jpg".to_string(),
+            }
+        );
+        let image: ImageRef = "image-a-b-10x20-png".parse().unwrap();
+        assert_eq!((image.id.as_str(), image.width, image.height), ("a-b", 10, 20));
+    }
+
+    #[test]
+    fn rejects_invalid_image_refs() {
+        for reference in [
+            "",
+            "file-abc-10x10-pdf",
+            "image-abc-10x10",
+            "image-abc-10-jpg",
+            "image--10x10-jpg",
+            "image-abc-10x10-",
+            "image-abc-0x10-jpg",
+            "image-abc-10xten-jpg",
+        ] {
+            assert_eq!(
+                reference.parse::<ImageRef>(),
+                Err(ImageError::InvalidRef(reference.to_string())),
+                "{:?}",
+                reference
+            );
+        }
+    }
+
+    #[test]
+    fn builds_urls_with_options() {
+        let url = image(100, 50).width(40).fit(Fit::Max).format(ImageFormat::Webp).quality(0).url();
+        assert_eq!(
+            url,
+            format!("{}/project/production/abc-100x50.png?w=40&fit=max&fm=webp&q=1", CDN_URL)
+        );
+        assert!(image(100, 50).quality(200).url().ends_with("?q=100"));
+        assert!(!image(100, 50).url().contains('?'));
+    }
+
+    #[test]
+    fn cuts_the_crop_out_of_the_image() {
+        let builder = image(1000, 500).crop(crop(0.1, 0.3, 0.1, 0.2));
+        assert_eq!(builder.rect(), Some((100, 50, 700, 300)));
+        assert!(builder.url().ends_with("?rect=100,50,700,300"));
+    }
+
+    #[test]
+    fn leaves_out_a_rect_covering_the_whole_image() {
+        assert_eq!(image(1000, 500).rect(), None);
+        assert_eq!(image(1000, 500).crop(crop(0.0, 0.0, 0.0, 0.0)).rect(), None);
+        assert_eq!(image(1000, 500).hotspot(hotspot(0.9, 0.9)).rect(), None);
+        // A target with the aspect ratio of the image keeps all of it
+        assert_eq!(image(1000, 500).hotspot(hotspot(0.9, 0.9)).width(200).height(100).rect(), None);
+    }
+
+    #[test]
+    fn centers_tall_targets_on_the_hotspot() {
+        let tall = |x| image(1000, 500).hotspot(hotspot(x, 0.5)).width(100).height(200);
+        assert_eq!(tall(0.5).rect(), Some((375, 0, 250, 500)));
+        assert_eq!(tall(0.05).rect(), Some((0, 0, 250, 500)));
+        assert_eq!(tall(0.95).rect(), Some((750, 0, 250, 500)));
+    }
+
+    #[test]
+    fn centers_wide_targets_on_the_hotspot() {
+        let wide = |y| image(500, 1000).hotspot(hotspot(0.5, y)).width(200).height(100);
+        assert_eq!(wide(0.5).rect(), Some((0, 375, 500, 250)));
+        assert_eq!(wide(0.05).rect(), Some((0, 0, 500, 250)));
+        assert_eq!(wide(0.95).rect(), Some((0, 750, 500, 250)));
+    }
+
+    #[test]
+    fn keeps_the_hotspot_rect_within_the_crop() {
+        let cropped = |x| {
+            image(1000, 500)
+                .crop(crop(0.0, 0.0, 0.2, 0.1))
+                .hotspot(hotspot(x, 0.5))
+                .width(100)
+                .height(200)
+        };
+        assert_eq!(cropped(0.05).rect(), Some((200, 0, 250, 500)));
+        assert_eq!(cropped(0.95).rect(), Some((650, 0, 250, 500)));
+    }
+
+    #[test]
+    fn ignores_the_hotspot_for_other_fits() {
+        let builder = image(1000, 500).hotspot(hotspot(0.9, 0.5)).width(100).height(200);
+        assert_eq!(builder.clone().fit(Fit::Crop).rect(), Some((750, 0, 250, 500)));
+        assert_eq!(builder.fit(Fit::Max).rect(), None);
+    }
+
+    #[test]
+    fn scales_srcset_heights_with_the_width() {
+        let srcset = image(1000, 500).width(400).height(200).srcset(&[200, 800]);
+        let base = format!("{}/project/production/abc-1000x500.png", CDN_URL);
+        assert_eq!(srcset, format!("{base}?w=200&h=100 200w, {base}?w=800&h=400 800w"));
+
+        let srcset = image(1000, 500).height(100).srcset(&[500]);
+        assert_eq!(srcset, format!("{base}?w=500&h=50 500w"));
+    }
+
+    #[test]
+    fn accepts_refs_assets_and_image_objects() {
+        let expected = urls().asset(REF).unwrap().url();
+        assert_eq!(urls().image(&json!(REF)).unwrap().url(), expected);
+        assert_eq!(urls().image(&json!({"_ref": REF})).unwrap().url(), expected);
+        assert_eq!(urls().image(&json!({"asset": {"_ref": REF}})).unwrap().url(), expected);
+
+        let image = json!({
+            "asset": {"_ref": REF},
+            "crop": {"top": 0.0, "bottom": 0.5, "left": 0.0, "right": 0.0},
+        });
+        assert!(urls().image(&image).unwrap().url().ends_with("?rect=0,0,2000,1500"));
+    }
+
+    #[test]
+    fn rejects_images_without_a_ref() {
+        for image in [json!({"asset": {}}), json!({"_type": "image"}), json!(1), json!("image")] {
+            assert!(urls().image(&image).is_err(), "{}", image);
+        }
+    }
+}
@@ -1 +1,12 @@
+pub mod cache;
+pub mod events;
+pub mod groq;
+pub mod image;
 pub mod model;
+pub mod portable_text;
+pub mod reload;
+pub mod sanity;
+pub mod session;
+pub mod shutdown;
+pub mod templates;
+pub mod throttle;
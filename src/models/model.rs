@@ -1,8 +1,13 @@
 /// A module defining various models and structures used in the application.
+use crate::models::groq::Direction;
+use crate::models::templates::TemplateFilters;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use futures::lock::Mutex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
-use tera::Tera;
+use std::sync::RwLock;
+use tera::{Context, Tera};
 
 /// Counter structure that holds an atomic integer.
 ///
@@ -16,8 +21,29 @@ pub struct Counter {
 ///
 /// This structure holds an instance of `Tera` which can be used to render
 /// templates. It is typically used in the context of web response rendering.
+/// The instance can be swapped at runtime to hot reload templates.
 pub struct TeraTemplates {
-    pub tera: Tera,
+    pub tera: RwLock<Tera>,
+    filters: TemplateFilters,
+}
+
+impl TeraTemplates {
+    /// Creates a new TeraTemplates instance, registering `filters` on `tera`.
+    pub fn new(mut tera: Tera, filters: TemplateFilters) -> Self {
+        filters.register(&mut tera);
+        TeraTemplates { tera: RwLock::new(tera), filters }
+    }
+
+    /// Renders a template with the given context.
+    pub fn render(&self, template: &str, context: &Context) -> tera::Result<String> {
+        self.tera.read().unwrap_or_else(|e| e.into_inner()).render(template, context)
+    }
+
+    /// Replaces the templates used by subsequent renders.
+    pub fn replace(&self, mut tera: Tera) {
+        self.filters.register(&mut tera);
+        *self.tera.write().unwrap_or_else(|e| e.into_inner()) = tera;
+    }
 }
 
 /// A struct representing a login request with email and password fields.
@@ -29,14 +55,76 @@ pub struct LoginRequest {
     pub password: String,
 }
 
-/// A struct representing a user.
+/// Query parameters Supabase appends when redirecting back from an OAuth provider.
 ///
-/// This includes user's basic information such as ID, name, and email.
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct User {
+/// On success only `code` is present; on failure Supabase sets `error` and
+/// `error_description` instead.
+#[derive(Deserialize)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Query parameters of the paginated `/api/sanity` item list.
+///
+/// All are optional; `cursor` is the `next_cursor` of the previous page.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ItemListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    /// Comma-separated fields to return, e.g. `name,slug.current`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<String>,
+}
+
+/// The position after the last item of a page, handed to clients as an opaque cursor.
+///
+/// Holds the sort the page was fetched with, so a cursor cannot be reused with
+/// another sort.
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct PageCursor {
+    pub sort: String,
+    pub direction: Direction,
+    pub value: serde_json::Value,
     pub id: String,
-    pub name: String,
-    pub email: String,
+}
+
+impl PageCursor {
+    /// Encodes the cursor as URL-safe base64 JSON.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Decodes a cursor created by `encode`, returning `None` if it is malformed.
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+/// A struct representing the body of a PKCE code exchange request.
+///
+/// The `auth_code` comes from the OAuth callback and the `code_verifier` is the
+/// secret generated when the flow was started.
+#[derive(Serialize)]
+pub struct PkceTokenRequest {
+    pub auth_code: String,
+    pub code_verifier: String,
+}
+
+/// A struct representing the body of a refresh token grant request.
+#[derive(Serialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
 }
 
 /// A struct representing the response from Supabase upon successful login.
@@ -55,7 +143,7 @@ pub struct SupabaseLoginResponse {
 /// A struct representing a user as returned by the Supabase authentication API.
 ///
 /// It includes detailed user information such as ID, email, roles, and metadata.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SupabaseUser {
     pub id: String,
     pub aud: String,
@@ -73,15 +161,32 @@ pub struct SupabaseUser {
     pub updated_at: String,
 }
 
+/// A struct representing the currently logged-in user as exposed to templates.
+///
+/// This is a trimmed-down view of `SupabaseUser` holding only what the UI needs.
+#[derive(Serialize, Debug, Clone)]
+pub struct CurrentUser {
+    pub id: String,
+    pub email: String,
+    pub role: String,
+}
+
+impl From<&SupabaseUser> for CurrentUser {
+    /// Creates a CurrentUser from the user returned by Supabase.
+    fn from(user: &SupabaseUser) -> Self {
+        CurrentUser { id: user.id.clone(), email: user.email.clone(), role: user.role.clone() }
+    }
+}
+
 /// A struct representing an identity associated with a Supabase user.
 ///
 /// This includes identity-specific details such as the provider and timestamps.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SupabaseIdentity {
     pub identity_id: String,
     pub id: String,
     pub user_id: String,
-    pub identity_data: HashMap<String, String>,
+    pub identity_data: HashMap<String, serde_json::Value>,
     pub provider: String,
     pub last_sign_in_at: Option<String>,
     pub created_at: Option<String>,
@@ -92,20 +197,42 @@ pub struct SupabaseIdentity {
 /// A struct representing an item, typically fetched from a database or API.
 ///
 /// This includes information about the item such as type, name, description, and related media.
+/// Besides the system fields, only the name and slug are required; Sanity does not
+/// enforce the other fields, so editors may leave them empty.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Item {
     pub _type: String,
-    pub question: String,
+    pub question: Option<String>,
     #[serde(rename = "_createdAt")]
     pub _created_at: String,
     pub name: String,
+    /// An unset flag counts as inactive.
+    #[serde(default)]
     pub active: bool,
-    pub description: String,
+    /// Plain text or Portable Text; render it with the `portable_text` filter.
+    pub description: Option<serde_json::Value>,
     pub _id: String,
     #[serde(rename = "_updatedAt")]
     pub _updated_at: String,
     pub slug: Slug,
-    pub image: Image,
+    /// An image without an asset, as the Studio leaves one after removing it,
+    /// counts as no image.
+    #[serde(default, deserialize_with = "image_with_asset")]
+    pub image: Option<Image>,
+}
+
+/// Deserializes an optional image, mapping one without an asset to `None`.
+fn image_with_asset<'de, D>(deserializer: D) -> Result<Option<Image>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    match value {
+        Some(value) if value.get("asset").is_some_and(|asset| !asset.is_null()) => {
+            serde_json::from_value(value).map(Some).map_err(serde::de::Error::custom)
+        }
+        _ => Ok(None),
+    }
 }
 
 /// A struct representing a slug, typically used in URLs or as identifiers.
@@ -117,11 +244,34 @@ pub struct Slug {
 
 /// A struct representing an image, usually associated with an item.
 ///
-/// It includes information about the image type and its asset.
+/// It includes information about the image type and its asset, plus the crop and
+/// hotspot chosen in the Studio, if any.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Image {
     pub _type: String,
     pub asset: Asset,
+    pub crop: Option<ImageCrop>,
+    pub hotspot: Option<ImageHotspot>,
+}
+
+/// The part of an image to keep, as fractions of its size cut from each side.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ImageCrop {
+    pub top: f64,
+    pub bottom: f64,
+    pub left: f64,
+    pub right: f64,
+}
+
+/// The area of an image to keep visible when cropping, as fractions of its size.
+///
+/// `x` and `y` are the center of the area.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ImageHotspot {
+    pub x: f64,
+    pub y: f64,
+    pub height: f64,
+    pub width: f64,
 }
 
 /// A struct representing an asset, which could be an image or other media type.
@@ -145,10 +295,3 @@ impl Navigation {
         Navigation { current_page: current_page.to_string() }
     }
 }
-
-/// A struct wrapping Sanity configuration for use in application contexts.
-///
-/// It contains a thread-safe, asynchronously-locked `SanityConfig`.
-pub struct MySanityConfig {
-    pub sanity_config: Mutex<sanity::SanityConfig>,
-}
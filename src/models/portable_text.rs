@@ -0,0 +1,350 @@
+/// Rendering of Sanity Portable Text to sanitized HTML.
+use crate::models::image::ImageUrls;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tera::escape_html;
+
+/// Widths of the `srcset` of embedded images.
+const IMAGE_WIDTHS: &[u32] = &[480, 960, 1440];
+
+/// Renders blocks of a custom type, such as a code snippet or a call to action.
+///
+/// The output is sanitized together with the rest of the document, so serializers
+/// may emit any HTML but only allowed tags and attributes are kept.
+pub trait BlockSerializer: Send + Sync {
+    /// Returns the HTML of `block`; `renderer` renders nested Portable Text.
+    fn serialize(&self, block: &Value, renderer: &PortableTextRenderer) -> String;
+}
+
+/// Renders Portable Text, Sanity's JSON format for rich text, to HTML.
+///
+/// Supports text blocks with the `normal`, `h1` to `h6` and `blockquote` styles,
+/// the `strong`, `em`, `code`, `underline` and `strike-through` decorators, link
+/// annotations, nested bullet and numbered lists, and embedded images. Other block
+/// types are rendered by the `BlockSerializer` registered for them and skipped
+/// otherwise. The result is sanitized with `ammonia`.
+///
+/// The renderer is also the `portable_text` Tera filter, whose output needs no
+/// `| safe`. The filter accepts plain strings, too.
+#[derive(Clone)]
+pub struct PortableTextRenderer {
+    images: Option<ImageUrls>,
+    serializers: HashMap<String, Arc<dyn BlockSerializer>>,
+}
+
+impl PortableTextRenderer {
+    /// Creates a renderer embedding images with `images`, or skipping them when `None`.
+    pub fn new(images: Option<ImageUrls>) -> Self {
+        PortableTextRenderer { images, serializers: HashMap::new() }
+    }
+
+    /// Renders blocks of `block_type` with `serializer`.
+    pub fn serializer(
+        mut self,
+        block_type: &str,
+        serializer: impl BlockSerializer + 'static,
+    ) -> Self {
+        self.serializers.insert(block_type.to_string(), Arc::new(serializer));
+        self
+    }
+
+    /// Renders Portable Text to sanitized HTML.
+    ///
+    /// `value` may be an array of blocks, a single block or a plain string.
+    pub fn render(&self, value: &Value) -> String {
+        let html = match value {
+            Value::Array(blocks) => self.blocks_to_html(blocks),
+            Value::Object(_) => self.blocks_to_html(std::slice::from_ref(value)),
+            Value::String(text) => format!("<p>{}</p>", text_to_html(text)),
+            _ => String::new(),
+        };
+        ammonia::Builder::default()
+            .add_tag_attributes("img", &["srcset", "sizes", "loading"])
+            .add_tag_attributes("code", &["class"])
+            .clean(&html)
+            .to_string()
+    }
+
+    /// Renders blocks to HTML without sanitizing it.
+    ///
+    /// For serializers rendering nested Portable Text; the output of `render` is
+    /// sanitized as a whole.
+    pub fn blocks_to_html(&self, blocks: &[Value]) -> String {
+        let mut html = String::new();
+        let mut open_lists: Vec<&str> = Vec::new();
+        for block in blocks {
+            let list_item = block.get("listItem").and_then(Value::as_str);
+            let Some(list_item) = list_item else {
+                close_lists(&mut html, &mut open_lists, 0);
+                html.push_str(&self.block_to_html(block));
+                continue;
+            };
+
+            let tag = if list_item == "number" { "ol" } else { "ul" };
+            let level = block.get("level").and_then(Value::as_u64).unwrap_or(1).max(1) as usize;
+            close_lists(&mut html, &mut open_lists, level);
+            if open_lists.len() == level && open_lists.last() != Some(&tag) {
+                close_lists(&mut html, &mut open_lists, level - 1);
+            }
+            if open_lists.len() == level {
+                html.push_str("</li>");
+            }
+            while open_lists.len() < level {
+                html.push_str(&format!("<{}>", tag));
+                open_lists.push(tag);
+            }
+            html.push_str("<li>");
+            html.push_str(&self.children_to_html(block));
+        }
+        close_lists(&mut html, &mut open_lists, 0);
+        html
+    }
+
+    /// Renders a block that is not a list item.
+    fn block_to_html(&self, block: &Value) -> String {
+        match block.get("_type").and_then(Value::as_str) {
+            Some("block") => {
+                let tag = block
+                    .get("style")
+                    .and_then(Value::as_str)
+                    .filter(|style| {
+                        matches!(*style, "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote")
+                    })
+                    .unwrap_or("p");
+                format!("<{}>{}</{}>", tag, self.children_to_html(block), tag)
+            }
+            Some(block_type) if self.serializers.contains_key(block_type) => {
+                self.serializers[block_type].serialize(block, self)
+            }
+            Some("image") => self.image_to_html(block),
+            block_type => {
+                log::debug!("No serializer for Portable Text block type {:?}", block_type);
+                String::new()
+            }
+        }
+    }
+
+    /// Renders the spans of a text block with their marks.
+    fn children_to_html(&self, block: &Value) -> String {
+        let mark_defs: HashMap<&str, &Value> = block
+            .get("markDefs")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|def| Some((def.get("_key")?.as_str()?, def)))
+            .collect();
+        let children = block.get("children").and_then(Value::as_array).into_iter().flatten();
+
+        let mut html = String::new();
+        for child in children {
+            let text = child.get("text").and_then(Value::as_str).unwrap_or_default();
+            let mut span = text_to_html(text);
+            let marks = child.get("marks").and_then(Value::as_array).into_iter().flatten();
+            // The first mark becomes the outermost element
+            for mark in marks.filter_map(Value::as_str).collect::<Vec<_>>().into_iter().rev() {
+                span = match mark_defs.get(mark) {
+                    Some(def) => annotate(def, span),
+                    None => decorate(mark, span),
+                };
+            }
+            html.push_str(&span);
+        }
+        html
+    }
+
+    /// Renders an embedded image, or nothing if Sanity is not configured.
+    fn image_to_html(&self, block: &Value) -> String {
+        let Some(images) = &self.images else { return String::new() };
+        match images.image(block) {
+            Ok(image) => {
+                let alt = block.get("alt").and_then(Value::as_str).unwrap_or_default();
+                format!(
+                    "<img src=\"{}\" srcset=\"{}\" alt=\"{}\" loading=\"lazy\">",
+                    escape_html(&image.clone().width(IMAGE_WIDTHS[1]).url()),
+                    escape_html(&image.srcset(IMAGE_WIDTHS)),
+                    escape_html(alt)
+                )
+            }
+            Err(e) => {
+                log::warn!("Skipping Portable Text image: {}", e);
+                String::new()
+            }
+        }
+    }
+}
+
+impl tera::Filter for PortableTextRenderer {
+    fn filter(&self, value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+        Ok(Value::from(self.render(value)))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// Renders `code` blocks of the Sanity code input plugin as `<pre><code>`.
+pub struct CodeBlock;
+
+impl BlockSerializer for CodeBlock {
+    fn serialize(&self, block: &Value, _renderer: &PortableTextRenderer) -> String {
+        let code = block.get("code").and_then(Value::as_str).unwrap_or_default();
+        let class = match block.get("language").and_then(Value::as_str) {
+            Some(language) => format!(" class=\"language-{}\"", escape_html(language)),
+            None => String::new(),
+        };
+        format!("<pre><code{}>{}</code></pre>", class, escape_html(code))
+    }
+}
+
+/// Closes open lists until only `depth` remain.
+fn close_lists(html: &mut String, open_lists: &mut Vec<&str>, depth: usize) {
+    while open_lists.len() > depth {
+        let tag = open_lists.pop().unwrap_or("ul");
+        html.push_str(&format!("</li></{}>", tag));
+    }
+}
+
+/// Escapes span text, keeping its line breaks.
+fn text_to_html(text: &str) -> String {
+    escape_html(text).replace('\n', "<br>")
+}
+
+/// Wraps `html` in the element of a decorator; unknown decorators are ignored.
+fn decorate(mark: &str, html: String) -> String {
+    let tag = match mark {
+        "strong" => "strong",
+        "em" => "em",
+        "code" => "code",
+        "underline" => "u",
+        "strike-through" => "s",
+        _ => return html,
+    };
+    format!("<{}>{}</{}>", tag, html, tag)
+}
+
+/// Wraps `html` in the element of an annotation; only links are supported.
+fn annotate(def: &Value, html: String) -> String {
+    match (def.get("_type").and_then(Value::as_str), def.get("href").and_then(Value::as_str)) {
+        (Some("link"), Some(href)) => format!("<a href=\"{}\">{}</a>", escape_html(href), html),
+        _ => html,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn renderer() -> PortableTextRenderer {
+        PortableTextRenderer::new(None).serializer("code", CodeBlock)
+    }
+
+    fn span(text: &str, marks: &[&str]) -> Value {
+        json!({"_type": "span", "text": text, "marks": marks})
+    }
+
+    fn block(children: Vec<Value>) -> Value {
+        json!({"_type": "block", "style": "normal", "children": children, "markDefs": []})
+    }
+
+    fn list_item(list: &str, level: u64, text: &str) -> Value {
+        json!({
+            "_type": "block",
+            "listItem": list,
+            "level": level,
+            "children": [span(text, &[])],
+        })
+    }
+
+    #[test]
+    fn renders_styles_and_plain_strings() {
+        let mut heading = block(vec![span("Title", &[])]);
+        heading["style"] = json!("h2");
+        assert_eq!(renderer().render(&json!([heading])), "<h2>Title</h2>");
+        assert_eq!(renderer().render(&json!("a < b\nc")), "<p>a &lt; b<br>c</p>");
+        assert_eq!(renderer().render(&json!(1)), "");
+    }
+
+    #[test]
+    fn nests_lists_by_level() {
+        let html = renderer().render(&json!([
+            list_item("bullet", 1, "a"),
+            list_item("bullet", 2, "b"),
+            list_item("bullet", 3, "c"),
+            list_item("bullet", 1, "d"),
+        ]));
+        assert_eq!(html, "<ul><li>a<ul><li>b<ul><li>c</li></ul></li></ul></li><li>d</li></ul>");
+    }
+
+    #[test]
+    fn starts_a_new_list_when_the_type_changes() {
+        let html = renderer().render(&json!([
+            list_item("bullet", 1, "a"),
+            list_item("number", 1, "b"),
+            list_item("number", 1, "c"),
+            block(vec![span("after", &[])]),
+        ]));
+        assert_eq!(html, "<ul><li>a</li></ul><ol><li>b</li><li>c</li></ol><p>after</p>");
+    }
+
+    #[test]
+    fn nests_marks_in_their_order() {
+        let html = renderer().render(&block(vec![
+            span("both", &["strong", "em"]),
+            span(" plain ", &[]),
+            span("gone", &["unknown"]),
+        ]));
+        assert_eq!(html, "<p><strong><em>both</em></strong> plain gone</p>");
+    }
+
+    #[test]
+    fn renders_link_annotations() {
+        let mut paragraph = block(vec![span("docs", &["link1", "em"])]);
+        paragraph["markDefs"] =
+            json!([{"_key": "link1", "_type": "link", "href": "https://example.com/?a=1&b=2"}]);
+        assert_eq!(
+            renderer().render(&paragraph),
+            "<p><a href=\"https://example.com/?a=1&amp;b=2\" rel=\"noopener noreferrer\"><em>docs</em></a></p>"
+        );
+    }
+
+    #[test]
+    fn removes_javascript_links() {
+        let mut paragraph = block(vec![span("click", &["evil"])]);
+        paragraph["markDefs"] =
+            json!([{"_key": "evil", "_type": "link", "href": "javascript:alert(1)"}]);
+        let html = renderer().render(&paragraph);
+        assert!(!html.contains("javascript"), "{}", html);
+        assert!(html.contains("click"), "{}", html);
+    }
+
+    #[test]
+    fn skips_unknown_block_types() {
+        let html = renderer().render(&json!([
+            {"_type": "chart", "data": [1, 2]},
+            block(vec![span("text", &[])]),
+        ]));
+        assert_eq!(html, "<p>text</p>");
+    }
+
+    #[test]
+    fn escapes_registered_serializer_output() {
+        let html = renderer().render(&json!({
+            "_type": "code",
+            "language": "html",
+            "code": "<script>alert(1)</script>",
+        }));
+        assert_eq!(
+            html,
+            "<pre><code class=\"language-html\">&lt;script&gt;alert(1)&lt;/script&gt;</code></pre>"
+        );
+    }
+
+    #[test]
+    fn skips_images_without_image_urls() {
+        let image = json!({"_type": "image", "asset": {"_ref": "image-abc-10x10-png"}});
+        assert_eq!(renderer().render(&image), "");
+    }
+}
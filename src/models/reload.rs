@@ -0,0 +1,72 @@
+/// Template hot reload for development.
+use crate::models::model::TeraTemplates;
+use crate::models::templates;
+use actix_web::web::Data;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// How long to wait for more file events before reloading.
+///
+/// Editors often write a file in several steps, which should cause a single reload.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Reparses the templates when they change on disk and tells open browsers to reload.
+///
+/// A fresh `Tera` instance is only swapped in when every template parses, so a
+/// syntax error while editing keeps the last working templates and logs the error.
+pub struct TemplateReloader {
+    templates: Data<TeraTemplates>,
+    reloads: watch::Sender<u64>,
+}
+
+impl TemplateReloader {
+    /// Creates a new TemplateReloader updating `templates`.
+    pub fn new(templates: Data<TeraTemplates>) -> Self {
+        TemplateReloader { templates, reloads: watch::Sender::new(0) }
+    }
+
+    /// Reparses the templates and, on success, notifies subscribers.
+    pub fn reload(&self) {
+        match templates::load_from_disk() {
+            Ok(tera) => {
+                self.templates.replace(tera);
+                self.reloads.send_modify(|count| *count += 1);
+                log::info!("Reloaded templates");
+            }
+            Err(e) => log::error!("Cannot reload templates: {:?}", e),
+        }
+    }
+
+    /// Returns a receiver that is notified after every successful reload.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.reloads.subscribe()
+    }
+
+    /// Watches `dir` recursively and reloads the templates on every change.
+    ///
+    /// Must be called from within the Actix runtime. Watching stops when the
+    /// returned watcher is dropped.
+    pub fn watch(reloader: Data<Self>, dir: &str) -> notify::Result<RecommendedWatcher> {
+        let (changes, mut changed) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    let _ = changes.send(());
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Template watcher error: {}", e),
+            })?;
+        watcher.watch(Path::new(dir), RecursiveMode::Recursive)?;
+
+        actix_web::rt::spawn(async move {
+            while changed.recv().await.is_some() {
+                tokio::time::sleep(DEBOUNCE).await;
+                while changed.try_recv().is_ok() {}
+                reloader.reload();
+            }
+        });
+        Ok(watcher)
+    }
+}
@@ -0,0 +1,375 @@
+/// An async client for the Sanity query API.
+use crate::configs::config::SanityConfig;
+use crate::models::cache::QueryCache;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Delay before the first retry; every further retry doubles it.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Longest delay between two retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// An error raised while querying Sanity.
+///
+/// Errors are cloneable so a failed query can be reported to every caller waiting on it.
+#[derive(Clone, Debug)]
+pub enum SanityError {
+    /// The request could not be sent or timed out.
+    Request(Arc<reqwest::Error>),
+    /// Sanity answered with an error status.
+    Status(StatusCode, String),
+    /// The response body does not have the expected shape.
+    Decode(String),
+}
+
+impl SanityError {
+    /// Returns true for errors that may go away when the query is retried.
+    fn is_transient(&self) -> bool {
+        match self {
+            SanityError::Request(e) => e.is_timeout() || e.is_connect(),
+            SanityError::Status(status, _) => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            SanityError::Decode(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for SanityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanityError::Request(e) => write!(f, "request failed: {}", e),
+            SanityError::Status(status, body) => {
+                write!(f, "unexpected status {}: {}", status, body)
+            }
+            SanityError::Decode(message) => write!(f, "invalid response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SanityError {}
+
+impl From<reqwest::Error> for SanityError {
+    fn from(error: reqwest::Error) -> Self {
+        SanityError::Request(Arc::new(error))
+    }
+}
+
+/// A document of a query result that could not be deserialized.
+#[derive(Debug, Serialize)]
+pub struct DocumentWarning {
+    /// The `_id` of the document, if it has one.
+    pub id: Option<String>,
+    pub error: String,
+}
+
+/// Deserializes each of `documents` on its own, so one invalid document does not
+/// fail the others.
+///
+/// Returns the valid documents and a warning for each invalid one, and logs the
+/// ids of the invalid ones.
+pub fn decode_documents<T: DeserializeOwned>(
+    documents: Vec<Value>,
+) -> (Vec<T>, Vec<DocumentWarning>) {
+    let mut decoded = Vec::with_capacity(documents.len());
+    let mut warnings = Vec::new();
+    for document in documents {
+        let id = document.get("_id").and_then(Value::as_str).map(str::to_string);
+        match T::deserialize(&document) {
+            Ok(value) => decoded.push(value),
+            Err(e) => {
+                log::warn!(
+                    "Skipping Sanity document {}: {}",
+                    id.as_deref().unwrap_or("without _id"),
+                    e
+                );
+                warnings.push(DocumentWarning { id, error: e.to_string() });
+            }
+        }
+    }
+    (decoded, warnings)
+}
+
+/// The envelope Sanity wraps query results in.
+#[derive(Deserialize)]
+struct QueryResponse<T> {
+    result: T,
+}
+
+/// A client for the Sanity query API.
+///
+/// Uses a pooled `reqwest::Client`, so queries run concurrently without blocking
+/// a worker thread. Every request is bounded by the configured timeout, and
+/// queries failing with timeouts, connection errors, 429 or 5xx responses are
+/// retried with exponential backoff. Results are cached by query and parameters
+/// as configured in `SanityConfig`.
+pub struct SanityClient {
+    api: QueryApi,
+    cache: QueryCache<Arc<Value>, SanityError>,
+}
+
+impl SanityClient {
+    /// Creates a client for the project and dataset in `config`.
+    pub fn new(config: &SanityConfig) -> Result<Self, SanityError> {
+        let http = reqwest::Client::builder().timeout(config.timeout).build()?;
+        let host = if config.use_cdn { "apicdn" } else { "api" };
+        Ok(SanityClient {
+            api: QueryApi {
+                http,
+                query_url: format!(
+                    "https://{}.{}.sanity.io/{}/data/query/{}",
+                    config.project_id, host, config.api_version, config.dataset
+                ),
+                token: config.token.clone(),
+                max_retries: config.max_retries,
+            },
+            cache: QueryCache::new(
+                config.cache_ttl,
+                config.cache_stale_while_revalidate,
+                config.cache_max_entries,
+            ),
+        })
+    }
+
+    /// Runs a GROQ query and deserializes its result.
+    ///
+    /// `params` bind the `$name` parameters used in the query.
+    pub async fn query<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        params: &Map<String, Value>,
+    ) -> Result<T, SanityError> {
+        let url_params = url_params(query, params);
+        let key = cache_key(&url_params);
+        let api = self.api.clone();
+        let result = self.cache.get_or_fetch(&key, move || api.query(url_params)).await?;
+        T::deserialize(result.as_ref()).map_err(|e| SanityError::Decode(e.to_string()))
+    }
+
+    /// Runs a GROQ query returning an array and deserializes each document on its own.
+    ///
+    /// Invalid documents are skipped and reported as warnings, see `decode_documents`.
+    pub async fn query_documents<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        params: &Map<String, Value>,
+    ) -> Result<(Vec<T>, Vec<DocumentWarning>), SanityError> {
+        let documents: Vec<Value> = self.query(query, params).await?;
+        Ok(decode_documents(documents))
+    }
+
+    /// Drops the cached results of queries that may involve a changed document.
+    ///
+    /// These are the queries mentioning the document's type or id as a string, and
+    /// those not filtering by type at all. Without a type or id every result is
+    /// dropped. Returns how many results were dropped.
+    pub fn invalidate_document(&self, doc_type: Option<&str>, id: Option<&str>) -> usize {
+        let published_id = id.map(|id| id.strip_prefix("drafts.").unwrap_or(id));
+        let literals: Vec<String> = [doc_type, id, published_id]
+            .into_iter()
+            .flatten()
+            .map(|value| Value::from(value).to_string())
+            .collect();
+        self.cache.invalidate(|key| {
+            literals.is_empty()
+                || !key.contains("_type")
+                || literals.iter().any(|literal| key.contains(literal))
+        })
+    }
+}
+
+/// Returns the URL parameters of a query: the query itself and its `$name` bindings.
+fn url_params(query: &str, params: &Map<String, Value>) -> Vec<(String, String)> {
+    let mut url_params = vec![("query".to_string(), query.to_string())];
+    url_params.extend(params.iter().map(|(name, value)| (format!("${}", name), value.to_string())));
+    url_params
+}
+
+/// Returns the cache key of a query, one `name=value` line per URL parameter.
+///
+/// Parameter values are JSON, so string literals appear quoted as in the query.
+fn cache_key(url_params: &[(String, String)]) -> String {
+    url_params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The uncached query endpoint of a `SanityClient`.
+#[derive(Clone)]
+struct QueryApi {
+    http: reqwest::Client,
+    query_url: String,
+    token: String,
+    max_retries: u32,
+}
+
+impl QueryApi {
+    /// Runs a query, retrying transient failures.
+    async fn query(self, url_params: Vec<(String, String)>) -> Result<Arc<Value>, SanityError> {
+        let mut attempt = 0;
+        loop {
+            match self.fetch(&url_params).await {
+                Err(e) if e.is_transient() && attempt < self.max_retries => {
+                    let delay = RETRY_BASE_DELAY
+                        .saturating_mul(2u32.saturating_pow(attempt))
+                        .min(MAX_RETRY_DELAY);
+                    log::warn!("Sanity query failed, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result.map(Arc::new),
+            }
+        }
+    }
+
+    /// Sends a single query request.
+    async fn fetch(&self, url_params: &[(String, String)]) -> Result<Value, SanityError> {
+        let response = self
+            .http
+            .get(&self.query_url)
+            .query(url_params)
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(SanityError::Status(status, body));
+        }
+        let body = response.bytes().await?;
+        serde_json::from_slice::<QueryResponse<Value>>(&body)
+            .map(|response| response.result)
+            .map_err(|e| SanityError::Decode(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::groq::{GroqQuery, Op};
+    use crate::models::model::Item;
+    use serde_json::json;
+
+    fn client() -> SanityClient {
+        SanityClient::new(&SanityConfig {
+            project_id: "project".to_string(),
+            dataset: "test".to_string(),
+            token: "token".to_string(),
+            api_version: "v2021-10-21".to_string(),
+            use_cdn: false,
+            timeout: Duration::from_secs(1),
+            max_retries: 0,
+            cache_ttl: Duration::from_secs(60),
+            cache_stale_while_revalidate: Duration::ZERO,
+            cache_max_entries: 100,
+            webhook_secret: None,
+        })
+        .unwrap()
+    }
+
+    /// Caches a result for `query`, returning its cache key.
+    async fn cache(client: &SanityClient, query: GroqQuery) -> String {
+        let (query, params) = query.build().unwrap();
+        let key = cache_key(&url_params(&query, &params));
+        client.cache.get_or_fetch(&key, || async { Ok(Arc::new(json!([]))) }).await.unwrap();
+        key
+    }
+
+    /// Caches the queries used by the tests: items, posts, and two queries not
+    /// filtering by type, by id and by slug.
+    async fn cache_queries(client: &SanityClient) {
+        cache(client, GroqQuery::of_type("item")).await;
+        cache(client, GroqQuery::new().filter("_id", Op::Eq, "abc")).await;
+        cache(client, GroqQuery::of_type("post")).await;
+        cache(client, GroqQuery::new().filter("slug.current", Op::Eq, "hello")).await;
+    }
+
+    #[actix_web::test]
+    async fn invalidates_queries_of_the_document_type() {
+        let client = client();
+        cache_queries(&client).await;
+        // The item query and the two untyped ones
+        assert_eq!(client.invalidate_document(Some("item"), None), 3);
+        assert_eq!(client.invalidate_document(Some("item"), None), 0);
+        assert_eq!(client.invalidate_document(Some("post"), None), 1);
+    }
+
+    #[actix_web::test]
+    async fn invalidates_queries_of_the_document_id() {
+        let client = client();
+        cache_queries(&client).await;
+        // The query by id and the one by slug, which may match any document
+        assert_eq!(client.invalidate_document(None, Some("abc")), 2);
+        assert_eq!(client.invalidate_document(None, Some("other")), 0);
+    }
+
+    #[actix_web::test]
+    async fn invalidates_queries_of_the_published_id_for_drafts() {
+        let client = client();
+        cache_queries(&client).await;
+        assert_eq!(client.invalidate_document(Some("page"), Some("drafts.abc")), 2);
+    }
+
+    #[actix_web::test]
+    async fn does_not_match_ids_inside_other_strings() {
+        let client = client();
+        cache(&client, GroqQuery::of_type("item").filter("_id", Op::Eq, "abcdef")).await;
+        assert_eq!(client.invalidate_document(Some("page"), Some("abc")), 0);
+    }
+
+    #[actix_web::test]
+    async fn invalidates_everything_without_type_or_id() {
+        let client = client();
+        cache_queries(&client).await;
+        assert_eq!(client.invalidate_document(None, None), 4);
+    }
+
+    #[test]
+    fn skips_invalid_documents() {
+        #[derive(Deserialize)]
+        struct Named {
+            name: String,
+        }
+        let (valid, warnings) = decode_documents::<Named>(vec![
+            json!({"_id": "a", "name": "A"}),
+            json!({"_id": "b"}),
+            json!({"name": 1}),
+        ]);
+        assert_eq!(valid.iter().map(|named| named.name.as_str()).collect::<Vec<_>>(), ["A"]);
+        assert_eq!(warnings.iter().map(|w| w.id.as_deref()).collect::<Vec<_>>(), [Some("b"), None]);
+    }
+
+    #[test]
+    fn decodes_image_without_asset_as_no_image() {
+        let item = |image: Value| {
+            json!({
+                "_id": "a",
+                "_type": "item",
+                "_createdAt": "2024-01-01T00:00:00Z",
+                "_updatedAt": "2024-01-01T00:00:00Z",
+                "name": "A",
+                "slug": {"_type": "slug", "current": "a"},
+                "image": image,
+            })
+        };
+        let (valid, warnings) = decode_documents::<Item>(vec![
+            item(json!({"_type": "image"})),
+            item(json!({"_type": "image", "asset": null})),
+            item(
+                json!({"_type": "image", "asset": {"_type": "reference", "_ref": "image-x-10x10-jpg"}}),
+            ),
+            item(json!({"_type": "image", "asset": "not an asset"})),
+        ]);
+        let images: Vec<bool> = valid.iter().map(|item| item.image.is_some()).collect();
+        assert_eq!(images, [false, false, true]);
+        assert_eq!(warnings.len(), 1);
+    }
+}
@@ -0,0 +1,255 @@
+/// Server-side sessions mapping opaque session ids to Supabase tokens.
+use crate::models::model::SupabaseUser;
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use postgrest::Postgrest;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the cookie holding the opaque session id.
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// How long after its access token expired a session may still be refreshed.
+///
+/// Sessions idle for longer count as expired and are dropped by the session stores.
+const IDLE_TIMEOUT_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// A server-side session.
+///
+/// The browser only ever sees the opaque `id`; the Supabase tokens and the user
+/// they belong to stay on the server so a session can be revoked at any time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: i64,
+    pub created_at: i64,
+    pub user_agent: Option<String>,
+    pub user: SupabaseUser,
+}
+
+impl Session {
+    /// Creates a new session with a random id.
+    pub fn new(
+        user: SupabaseUser,
+        access_token: String,
+        refresh_token: String,
+        expires_at: i64,
+        user_agent: Option<String>,
+    ) -> Self {
+        let mut id = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut id);
+        Session {
+            id: URL_SAFE_NO_PAD.encode(id),
+            user_id: user.id.clone(),
+            access_token,
+            refresh_token,
+            expires_at,
+            created_at: unix_now(),
+            user_agent,
+            user,
+        }
+    }
+
+    /// Returns the handle identifying the session to its user, e.g. to revoke it.
+    ///
+    /// The id is the credential behind the session cookie, so pages show this
+    /// SHA-256 of it instead.
+    pub fn handle(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.id.as_bytes()))
+    }
+
+    /// Returns true when the access token expires within the next minute.
+    pub fn needs_refresh(&self) -> bool {
+        self.expires_at <= unix_now() + 60
+    }
+
+    /// Returns true when the session has been idle for longer than `IDLE_TIMEOUT_SECS`.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= expiry_cutoff()
+    }
+}
+
+/// Returns the `expires_at` up to which sessions count as expired.
+fn expiry_cutoff() -> i64 {
+    unix_now() - IDLE_TIMEOUT_SECS
+}
+
+/// A view of a session that is safe to expose to templates.
+///
+/// Leaves out the session id and the Supabase tokens, and flags the session of the
+/// current request.
+#[derive(Serialize)]
+pub struct SessionSummary {
+    pub handle: String,
+    pub created_at: i64,
+    pub user_agent: Option<String>,
+    pub current: bool,
+}
+
+impl SessionSummary {
+    /// Creates a summary of `session`, marking it as current if its id matches `current_id`.
+    pub fn new(session: &Session, current_id: &str) -> Self {
+        SessionSummary {
+            handle: session.handle(),
+            created_at: session.created_at,
+            user_agent: session.user_agent.clone(),
+            current: session.id == current_id,
+        }
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+/// An error raised by a session store backend.
+#[derive(Debug)]
+pub struct SessionStoreError(pub String);
+
+impl fmt::Display for SessionStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "session store error: {}", self.0)
+    }
+}
+
+impl std::error::Error for SessionStoreError {}
+
+/// A pluggable backend for persisting sessions.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Inserts a session, replacing any existing session with the same id.
+    async fn save(&self, session: &Session) -> Result<(), SessionStoreError>;
+
+    /// Looks up a session by id.
+    async fn get(&self, id: &str) -> Result<Option<Session>, SessionStoreError>;
+
+    /// Lists all active sessions of a user, oldest first.
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<Session>, SessionStoreError>;
+
+    /// Removes a session. Removing an unknown session is not an error.
+    async fn remove(&self, id: &str) -> Result<(), SessionStoreError>;
+}
+
+/// A session store keeping sessions in process memory.
+///
+/// Sessions are lost on restart and not shared between instances, which makes
+/// this store suitable for development and single-instance deployments. Expired
+/// sessions are dropped whenever a session is saved or listed.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn save(&self, session: &Session) -> Result<(), SessionStoreError> {
+        let mut sessions = self.sessions.write().map_err(|e| SessionStoreError(e.to_string()))?;
+        sessions.retain(|_, session| !session.is_expired());
+        sessions.insert(session.id.clone(), session.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, SessionStoreError> {
+        let sessions = self.sessions.read().map_err(|e| SessionStoreError(e.to_string()))?;
+        Ok(sessions.get(id).filter(|session| !session.is_expired()).cloned())
+    }
+
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<Session>, SessionStoreError> {
+        let mut sessions = self.sessions.write().map_err(|e| SessionStoreError(e.to_string()))?;
+        sessions.retain(|_, session| !session.is_expired());
+        let mut user_sessions: Vec<Session> =
+            sessions.values().filter(|s| s.user_id == user_id).cloned().collect();
+        user_sessions.sort_by_key(|s| s.created_at);
+        Ok(user_sessions)
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), SessionStoreError> {
+        let mut sessions = self.sessions.write().map_err(|e| SessionStoreError(e.to_string()))?;
+        sessions.remove(id);
+        Ok(())
+    }
+}
+
+/// A session store persisting sessions in a Postgres table through PostgREST.
+///
+/// Expects a `sessions` table shaped like `Session`:
+///
+/// ```sql
+/// create table sessions (
+///   id text primary key,
+///   user_id uuid not null,
+///   access_token text not null,
+///   refresh_token text not null,
+///   expires_at bigint not null,
+///   created_at bigint not null,
+///   user_agent text,
+///   "user" jsonb not null
+/// );
+/// ```
+///
+/// The client must be authorized with a key allowed to bypass row level security,
+/// since the table holds tokens for every user. Expired sessions are never returned
+/// and are deleted whenever a session is saved.
+pub struct PostgrestSessionStore {
+    client: Postgrest,
+}
+
+impl PostgrestSessionStore {
+    /// Creates a store using the given PostgREST client.
+    pub fn new(client: Postgrest) -> Self {
+        PostgrestSessionStore { client }
+    }
+
+    /// Executes a request and deserializes the returned rows.
+    async fn rows(&self, builder: postgrest::Builder) -> Result<Vec<Session>, SessionStoreError> {
+        let response = builder.execute().await.map_err(|e| SessionStoreError(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(SessionStoreError(format!("unexpected status {}", response.status())));
+        }
+        response.json::<Vec<Session>>().await.map_err(|e| SessionStoreError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl SessionStore for PostgrestSessionStore {
+    async fn save(&self, session: &Session) -> Result<(), SessionStoreError> {
+        let body = serde_json::to_string(session).map_err(|e| SessionStoreError(e.to_string()))?;
+        let cutoff = expiry_cutoff().to_string();
+        self.rows(self.client.from("sessions").delete().lte("expires_at", cutoff)).await?;
+        self.rows(self.client.from("sessions").upsert(body)).await.map(|_| ())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, SessionStoreError> {
+        let cutoff = expiry_cutoff().to_string();
+        let rows = self
+            .rows(self.client.from("sessions").select("*").eq("id", id).gt("expires_at", cutoff))
+            .await?;
+        Ok(rows.into_iter().next())
+    }
+
+    async fn list_for_user(&self, user_id: &str) -> Result<Vec<Session>, SessionStoreError> {
+        self.rows(
+            self.client
+                .from("sessions")
+                .select("*")
+                .eq("user_id", user_id)
+                .gt("expires_at", expiry_cutoff().to_string())
+                .order("created_at"),
+        )
+        .await
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), SessionStoreError> {
+        self.rows(self.client.from("sessions").delete().eq("id", id)).await.map(|_| ())
+    }
+}
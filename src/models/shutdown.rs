@@ -0,0 +1,44 @@
+/// Coordination of a graceful shutdown across long-lived connections.
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Notifies long-lived connections that the server is about to stop.
+///
+/// WebSocket actors and SSE streams subscribe to the coordinator. When a shutdown
+/// starts they are told to close with a reason so clients can reconnect to another
+/// instance, and the coordinator then waits `drain_period` before the server stops.
+pub struct ShutdownCoordinator {
+    sender: watch::Sender<bool>,
+    drain_period: Duration,
+}
+
+impl ShutdownCoordinator {
+    /// Creates a new ShutdownCoordinator.
+    pub fn new(drain_period: Duration) -> Self {
+        ShutdownCoordinator { sender: watch::Sender::new(false), drain_period }
+    }
+
+    /// Returns a signal that resolves once a shutdown has started.
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal(self.sender.subscribe())
+    }
+
+    /// Notifies all subscribers and waits for the drain period to pass.
+    pub async fn shutdown(&self) {
+        log::info!("Shutting down, draining connections for {:?}", self.drain_period);
+        self.sender.send_replace(true);
+        tokio::time::sleep(self.drain_period).await;
+    }
+}
+
+/// A subscription to a `ShutdownCoordinator`.
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Waits until a shutdown has started.
+    ///
+    /// Resolves immediately if the shutdown started before this call.
+    pub async fn recv(mut self) {
+        let _ = self.0.wait_for(|shutting_down| *shutting_down).await;
+    }
+}
@@ -0,0 +1,213 @@
+/// Loading and checking of the Tera templates.
+use crate::models::image::{Fit, ImageError, ImageFormat, ImageUrlBuilder, ImageUrls};
+use crate::models::portable_text::PortableTextRenderer;
+use serde_json::Value;
+use std::collections::HashMap;
+use tera::ast::Node;
+use tera::Tera;
+
+/// Directory holding the Tera templates.
+pub const TEMPLATES_DIR: &str = "templates";
+
+/// Glob matching every Tera template.
+const TEMPLATES_GLOB: &str = "templates/**/*";
+
+/// Templates compiled into the binary with the `embed-templates` feature.
+#[cfg(feature = "embed-templates")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "templates/"]
+struct EmbeddedTemplates;
+
+/// Parses the templates in `templates/` relative to the working directory.
+pub fn load_from_disk() -> tera::Result<Tera> {
+    Tera::new(TEMPLATES_GLOB)
+}
+
+/// Parses the templates compiled into the binary.
+#[cfg(feature = "embed-templates")]
+pub fn load_embedded() -> tera::Result<Tera> {
+    let mut templates = Vec::new();
+    for name in EmbeddedTemplates::iter() {
+        let Some(file) = EmbeddedTemplates::get(&name) else { continue };
+        let content = String::from_utf8(file.data.into_owned())
+            .map_err(|e| tera::Error::msg(format!("Template {} is not UTF-8: {}", name, e)))?;
+        templates.push((name.into_owned(), content));
+    }
+    let mut tera = Tera::default();
+    tera.add_raw_templates(templates)?;
+    Ok(tera)
+}
+
+/// Loads the templates the server starts with.
+///
+/// Binaries built with the `embed-templates` feature use the embedded templates,
+/// unless `prefer_disk` is set (for hot reload in development) and `templates/`
+/// exists. Without the feature the templates are always read from disk.
+#[cfg_attr(not(feature = "embed-templates"), allow(unused_variables))]
+pub fn load(prefer_disk: bool) -> tera::Result<Tera> {
+    #[cfg(feature = "embed-templates")]
+    if !prefer_disk || !std::path::Path::new(TEMPLATES_DIR).is_dir() {
+        log::info!("Using the embedded templates");
+        return load_embedded();
+    }
+    load_from_disk()
+}
+
+/// Filters available to every template.
+///
+/// Registered by `TeraTemplates` on the initial templates and on every reload.
+#[derive(Clone)]
+pub struct TemplateFilters {
+    /// Builds the URLs of the image filters; `None` when Sanity is not configured.
+    pub images: Option<ImageUrls>,
+    /// The `portable_text` filter.
+    pub portable_text: PortableTextRenderer,
+}
+
+impl TemplateFilters {
+    /// Registers the filters on `tera`.
+    ///
+    /// - `sanity_image(width, height, fit, format, quality)` turns a Sanity image,
+    ///   asset or asset reference into a CDN URL.
+    /// - `sanity_srcset(widths, height, fit, format, quality)` returns a `srcset`
+    ///   value with a URL for each of `widths`.
+    ///
+    /// Both return an empty string for a missing or invalid image.
+    ///
+    /// - `portable_text` renders Portable Text to sanitized HTML.
+    pub fn register(&self, tera: &mut Tera) {
+        tera.register_filter("portable_text", self.portable_text.clone());
+        let images = self.images.clone();
+        tera.register_filter(
+            "sanity_image",
+            move |image: &Value, args: &HashMap<String, Value>| {
+                let url = image_url(images.as_ref(), image, args)?.map(|builder| builder.url());
+                Ok(Value::from(url.unwrap_or_default()))
+            },
+        );
+        let images = self.images.clone();
+        tera.register_filter(
+            "sanity_srcset",
+            move |image: &Value, args: &HashMap<String, Value>| {
+                let widths = match args.get("widths") {
+                    Some(widths) => tera::from_value::<Vec<u32>>(widths.clone())?,
+                    None => return Err(tera::Error::msg("sanity_srcset requires `widths`")),
+                };
+                let srcset = image_url(images.as_ref(), image, args)?.map(|b| b.srcset(&widths));
+                Ok(Value::from(srcset.unwrap_or_default()))
+            },
+        );
+    }
+}
+
+/// Starts the URL of an image filter call, or returns `None` for a missing image.
+///
+/// Invalid arguments fail the render. An invalid image is logged and treated as
+/// missing, so one bad document does not break the page.
+fn image_url(
+    images: Option<&ImageUrls>,
+    image: &Value,
+    args: &HashMap<String, Value>,
+) -> tera::Result<Option<ImageUrlBuilder>> {
+    let invalid = |e: ImageError| tera::Error::msg(e.to_string());
+    let arg = |name: &str| args.get(name).cloned();
+    let width = arg("width").map(tera::from_value::<u32>).transpose()?;
+    let height = arg("height").map(tera::from_value::<u32>).transpose()?;
+    let fit = match arg("fit") {
+        Some(fit) => Some(tera::from_value::<String>(fit)?.parse::<Fit>().map_err(invalid)?),
+        None => None,
+    };
+    let format = match arg("format") {
+        Some(format) => {
+            Some(tera::from_value::<String>(format)?.parse::<ImageFormat>().map_err(invalid)?)
+        }
+        None => None,
+    };
+    let quality = arg("quality").map(tera::from_value::<u8>).transpose()?;
+
+    if image.is_null() {
+        return Ok(None);
+    }
+    let images = images.ok_or_else(|| tera::Error::msg("Sanity is not configured"))?;
+    let mut builder = match images.image(image) {
+        Ok(builder) => builder,
+        Err(e) => {
+            log::warn!("Skipping Sanity image: {}", e);
+            return Ok(None);
+        }
+    };
+    if let Some(width) = width {
+        builder = builder.width(width);
+    }
+    if let Some(height) = height {
+        builder = builder.height(height);
+    }
+    if let Some(fit) = fit {
+        builder = builder.fit(fit);
+    }
+    if let Some(format) = format {
+        builder = builder.format(format);
+    }
+    if let Some(quality) = quality {
+        builder = builder.quality(quality);
+    }
+    Ok(Some(builder))
+}
+
+/// Reports template names that are referenced but do not exist.
+///
+/// Checks the names in `referenced` (the templates rendered by handlers) and every
+/// `{% include %}` in the parsed templates; `extends` is already verified by Tera
+/// when parsing. An include listing several templates only needs one of them.
+pub fn missing_templates(tera: &Tera, referenced: &[&str]) -> Vec<String> {
+    let exists = |name: &str| tera.templates.contains_key(name);
+    let mut problems: Vec<String> = referenced
+        .iter()
+        .filter(|name| !exists(name))
+        .map(|name| format!("{} is rendered by a handler but does not exist", name))
+        .collect();
+
+    let mut names: Vec<&String> = tera.templates.keys().collect();
+    names.sort();
+    for name in names {
+        let mut includes = Vec::new();
+        collect_includes(&tera.templates[name].ast, &mut includes);
+        for include in includes.into_iter().filter(|include| !include.iter().any(|i| exists(i))) {
+            problems.push(format!(
+                "{} includes {} which does not exist",
+                name,
+                include.join(" or ")
+            ));
+        }
+    }
+    problems
+}
+
+/// Collects the template names of every non-optional include in `nodes`.
+///
+/// Uses Tera's AST, which is public but not covered by its semver guarantees.
+fn collect_includes<'a>(nodes: &'a [Node], includes: &mut Vec<&'a [String]>) {
+    for node in nodes {
+        match node {
+            Node::Include(_, names, ignore_missing) if !ignore_missing => includes.push(names),
+            Node::Block(_, block, _) => collect_includes(&block.body, includes),
+            Node::MacroDefinition(_, definition, _) => collect_includes(&definition.body, includes),
+            Node::FilterSection(_, section, _) => collect_includes(&section.body, includes),
+            Node::Forloop(_, forloop, _) => {
+                collect_includes(&forloop.body, includes);
+                if let Some(empty_body) = &forloop.empty_body {
+                    collect_includes(empty_body, includes);
+                }
+            }
+            Node::If(condition, _) => {
+                for (_, _, body) in &condition.conditions {
+                    collect_includes(body, includes);
+                }
+                if let Some((_, otherwise)) = &condition.otherwise {
+                    collect_includes(otherwise, includes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/// Brute-force protection for the login endpoint.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Failed login attempts recorded for a single key.
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Tracks failed login attempts and locks out keys with exponential backoff.
+///
+/// Keys are typically an email address and a client IP, so an attacker can neither
+/// hammer a single account nor spray many accounts from one address. After
+/// `free_attempts` failures every further failure locks the key for
+/// `base_lockout * 2^n`, capped at `max_lockout`. Keys without failures for
+/// `max_lockout` are forgotten.
+pub struct LoginThrottle {
+    attempts: Mutex<HashMap<String, Attempts>>,
+    free_attempts: u32,
+    base_lockout: Duration,
+    max_lockout: Duration,
+}
+
+impl LoginThrottle {
+    /// Creates a new LoginThrottle.
+    pub fn new(free_attempts: u32, base_lockout: Duration, max_lockout: Duration) -> Self {
+        LoginThrottle {
+            attempts: Mutex::new(HashMap::new()),
+            free_attempts,
+            base_lockout,
+            max_lockout,
+        }
+    }
+
+    /// Returns how long the longest lockout among `keys` still lasts, if any key is locked.
+    pub fn retry_after(&self, keys: &[String]) -> Option<Duration> {
+        let attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        keys.iter()
+            .filter_map(|key| attempts.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// Records a failed attempt for every key.
+    ///
+    /// Returns the keys that got locked by this failure together with the lockout duration.
+    pub fn record_failure(&self, keys: &[String]) -> Vec<(String, Duration)> {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        attempts.retain(|_, a| now.duration_since(a.last_failure) < self.max_lockout);
+
+        let mut locked = Vec::new();
+        for key in keys {
+            let entry = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            entry.failures += 1;
+            entry.last_failure = now;
+            if entry.failures > self.free_attempts {
+                let exponent = (entry.failures - self.free_attempts - 1).min(16);
+                let lockout = self.base_lockout.saturating_mul(1 << exponent).min(self.max_lockout);
+                entry.locked_until = Some(now + lockout);
+                locked.push((key.clone(), lockout));
+            }
+        }
+        locked
+    }
+
+    /// Forgets the failed attempts of `keys` after a successful login.
+    pub fn record_success(&self, keys: &[String]) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        for key in keys {
+            attempts.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(10);
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    /// Returns the lockout of each of `n` failures of `key`, `None` for free attempts.
+    fn fail(throttle: &LoginThrottle, key: &str, n: usize) -> Vec<Option<Duration>> {
+        (0..n)
+            .map(|_| throttle.record_failure(&keys(&[key])).first().map(|(_, lockout)| *lockout))
+            .collect()
+    }
+
+    #[test]
+    fn locks_out_after_the_free_attempts() {
+        let throttle = LoginThrottle::new(2, BASE, Duration::from_secs(600));
+        assert_eq!(fail(&throttle, "a", 2), [None, None]);
+        assert_eq!(throttle.retry_after(&keys(&["a"])), None);
+
+        assert_eq!(throttle.record_failure(&keys(&["a"])), [("a".to_string(), BASE)]);
+        let retry_after = throttle.retry_after(&keys(&["a"])).unwrap();
+        assert!(retry_after > BASE - Duration::from_secs(1) && retry_after <= BASE);
+    }
+
+    #[test]
+    fn doubles_the_lockout_up_to_the_maximum() {
+        let throttle = LoginThrottle::new(0, BASE, Duration::from_secs(100));
+        let lockouts = fail(&throttle, "a", 6);
+        let seconds: Vec<u64> =
+            lockouts.into_iter().map(|lockout| lockout.unwrap().as_secs()).collect();
+        assert_eq!(seconds, [10, 20, 40, 80, 100, 100]);
+    }
+
+    #[test]
+    fn retries_after_the_longest_lockout_of_the_keys() {
+        let throttle = LoginThrottle::new(0, BASE, Duration::from_secs(600));
+        fail(&throttle, "email", 1);
+        fail(&throttle, "ip", 3);
+        let retry_after = throttle.retry_after(&keys(&["email", "ip"])).unwrap();
+        assert!(retry_after > Duration::from_secs(39), "{:?}", retry_after);
+        assert!(throttle.retry_after(&keys(&["email"])).unwrap() <= BASE);
+        assert_eq!(throttle.retry_after(&keys(&["other"])), None);
+    }
+
+    #[test]
+    fn forgets_only_the_keys_of_a_success() {
+        let throttle = LoginThrottle::new(0, BASE, Duration::from_secs(600));
+        throttle.record_failure(&keys(&["email", "ip"]));
+        throttle.record_success(&keys(&["email"]));
+        assert_eq!(throttle.retry_after(&keys(&["email"])), None);
+        assert!(throttle.retry_after(&keys(&["ip"])).is_some());
+        // The cleared key starts over
+        assert_eq!(fail(&throttle, "email", 1), [Some(BASE)]);
+    }
+}